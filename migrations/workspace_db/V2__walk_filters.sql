@@ -0,0 +1,7 @@
+alter table shared_folders add column skip_cache_dirs integer not null default 1;
+alter table shared_folders add column max_size integer;
+-- In seconds
+alter table shared_folders add column min_age integer;
+alter table shared_folders add column max_age integer;
+-- Comma separated user ids
+alter table shared_folders add column owners text;
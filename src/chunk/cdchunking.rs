@@ -1,4 +1,4 @@
-use std::fs;
+use std::io::Read;
 
 use anyhow::Result;
 
@@ -23,14 +23,14 @@ impl ChunkerImpl for ZPAQ {
         self.block_max
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         let chunker = ::cdchunking::Chunker::new(::cdchunking::ZPAQ::new(self.nbits)) //
             .max_size(self.block_max as usize);
 
-        for chunk in chunker.whole_chunks(file) {
+        for chunk in chunker.whole_chunks(reader) {
             let chunk = chunk?;
 
-            cb(chunk);
+            cb(chunk.into());
         }
 
         Ok(())
@@ -1,4 +1,5 @@
 use std::fs;
+use std::io::Read;
 
 use anyhow::{Context, Result};
 
@@ -25,8 +26,8 @@ impl ChunkerImpl for FastCDC2020Mmap {
         self.block_max
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let mmap = unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? };
+    fn split(&self, file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        let mmap = Arc::new(unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? });
 
         let chunker = ::fastcdc::v2020::FastCDC::new(
             &mmap[..], //
@@ -36,9 +37,25 @@ impl ChunkerImpl for FastCDC2020Mmap {
         );
 
         for chunk in chunker {
-            cb(Vec::from(&mmap[chunk.offset..chunk.offset + chunk.length]));
+            cb(ChunkData::new(mmap.clone(), chunk.offset..chunk.offset + chunk.length));
         }
 
         Ok(())
     }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        let chunker = ::fastcdc::v2020::StreamCDC::new(reader, self.block_min, self.block_avg, self.block_max);
+
+        for chunk in chunker {
+            cb(chunk?.data.into());
+        }
+
+        Ok(())
+    }
+
+    fn find_cut(&self, data: &[u8]) -> Option<usize> {
+        let chunker = ::fastcdc::v2020::FastCDC::new(data, self.block_min, self.block_avg, self.block_max);
+
+        Some(chunker.cut(0, data.len()).1)
+    }
 }
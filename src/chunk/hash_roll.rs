@@ -23,6 +23,10 @@ impl FastCdc {
             mmap,
         }
     }
+
+    fn config(&self) -> hr::fastcdc::FastCdc<'static> {
+        hr::fastcdc::FastCdc::new(&hr::gear_table::GEAR_64, self.block_min, self.block_normal, self.block_max)
+    }
 }
 
 impl ChunkerImpl for FastCdc {
@@ -30,16 +34,17 @@ impl ChunkerImpl for FastCdc {
         self.block_max as u32
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let cfg =
-            hr::fastcdc::FastCdc::new(&hr::gear_table::GEAR_64, self.block_min, self.block_normal, self.block_max);
-
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if self.mmap {
-            split_mmap(cfg, file, cb)
+            split_mmap(self.config(), file, cb)
         } else {
-            split_mem(cfg, file, cb)
+            split_mem(self.config(), &mut file, cb)
         }
     }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        split_mem(self.config(), reader, cb)
+    }
 }
 
 pub struct ZPAQ {
@@ -56,6 +61,10 @@ impl ZPAQ {
             mmap,
         }
     }
+
+    fn config(&self) -> hr::zpaq::Zpaq {
+        hr::zpaq::Zpaq::with_average_size_pow_2(self.bits)
+    }
 }
 
 impl ChunkerImpl for ZPAQ {
@@ -63,15 +72,17 @@ impl ChunkerImpl for ZPAQ {
         self.block_max
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let cfg = hr::zpaq::Zpaq::with_average_size_pow_2(self.bits);
-
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if self.mmap {
-            split_mmap(cfg, file, cb)
+            split_mmap(self.config(), file, cb)
         } else {
-            split_mem(cfg, file, cb)
+            split_mem(self.config(), &mut file, cb)
         }
     }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        split_mem(self.config(), reader, cb)
+    }
 }
 
 pub struct RollSum {
@@ -88,6 +99,10 @@ impl RollSum {
             mmap,
         }
     }
+
+    fn config(&self) -> hr::bup::RollSum {
+        hr::bup::RollSum::with_window(self.block_size as usize)
+    }
 }
 
 impl ChunkerImpl for RollSum {
@@ -95,15 +110,17 @@ impl ChunkerImpl for RollSum {
         self.block_max
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let cfg = hr::bup::RollSum::with_window(self.block_size as usize);
-
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if self.mmap {
-            split_mmap(cfg, file, cb)
+            split_mmap(self.config(), file, cb)
         } else {
-            split_mem(cfg, file, cb)
+            split_mem(self.config(), &mut file, cb)
         }
     }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        split_mem(self.config(), reader, cb)
+    }
 }
 
 pub struct RAM {
@@ -120,6 +137,10 @@ impl RAM {
             mmap,
         }
     }
+
+    fn config(&self) -> hr::ram::Ram {
+        hr::ram::Ram::with_w(self.block_size as u64)
+    }
 }
 
 impl ChunkerImpl for RAM {
@@ -127,25 +148,27 @@ impl ChunkerImpl for RAM {
         self.block_max
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let cfg = hr::ram::Ram::with_w(self.block_size as u64);
-
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if self.mmap {
-            split_mmap(cfg, file, cb)
+            split_mmap(self.config(), file, cb)
         } else {
-            split_mem(cfg, file, cb)
+            split_mem(self.config(), &mut file, cb)
         }
     }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        split_mem(self.config(), reader, cb)
+    }
 }
 
-fn split_mem(cfg: impl hr::ToChunkIncr, mut file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+fn split_mem(cfg: impl hr::ToChunkIncr, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
     let mut chunker = cfg.to_chunk_incr();
 
     let mut chunk = Vec::new();
 
     let mut buffer = [0; 1024];
     loop {
-        let size = match file.read(&mut buffer) {
+        let size = match reader.read(&mut buffer) {
             Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
             o => o?,
         };
@@ -157,7 +180,7 @@ fn split_mem(cfg: impl hr::ToChunkIncr, mut file: fs::File, cb: &mut dyn FnMut(V
         let mut start = 0;
         while let Some(separator) = chunker.push(&buffer[start..size]) {
             chunk.extend_from_slice(&buffer[start..start + separator]);
-            cb(chunk);
+            cb(chunk.into());
             chunk = Vec::new();
 
             start = start + separator;
@@ -168,26 +191,26 @@ fn split_mem(cfg: impl hr::ToChunkIncr, mut file: fs::File, cb: &mut dyn FnMut(V
     }
 
     if !chunk.is_empty() {
-        cb(chunk);
+        cb(chunk.into());
     }
 
     Ok(())
 }
 
-fn split_mmap(cfg: impl hr::ToChunkIncr, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+fn split_mmap(cfg: impl hr::ToChunkIncr, file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
     let mut chunker = cfg.to_chunk_incr();
 
-    let mmap = unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? };
+    let mmap = Arc::new(unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? });
 
     let mut start = 0;
 
     while let Some(separator) = chunker.push(&mmap[start..]) {
-        cb(Vec::from(&mmap[start..start + separator]));
+        cb(ChunkData::new(mmap.clone(), start..start + separator));
         start += separator;
     }
 
     if start < mmap.len() {
-        cb(Vec::from(&mmap[start..]));
+        cb(ChunkData::new(mmap.clone(), start..mmap.len()));
     }
 
     Ok(())
@@ -1,5 +1,8 @@
+use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
 use std::path;
 use std::sync::Arc;
 
@@ -9,6 +12,78 @@ mod cdchunking;
 mod fastcdc;
 mod hash_roll;
 mod rabin;
+mod sparse;
+
+/// Files with at least two segments of this size can be chunked in parallel.
+const PARALLEL_SEGMENT_SIZE: usize = 64 * 1024 * 1024;
+/// Files chunked by a single thread are read in segments of this size.
+const READ_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// The contents of a chunk. Chunks found in a mmap keep a reference to it instead of copying their bytes, until
+/// `into_owned` is called. The file can change or shrink under a mmap, so that must be done before the data is used.
+/// Chunks read from a file share the buffer it was read into instead, which can't change, so they are already owned.
+#[derive(Clone)]
+pub struct ChunkData {
+    buffer: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+    owned: bool,
+}
+
+impl ChunkData {
+    pub fn new(buffer: Arc<dyn AsRef<[u8]> + Send + Sync>, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= (*buffer).as_ref().len());
+
+        Self {
+            buffer,
+            range,
+            owned: false,
+        }
+    }
+
+    fn shared(buffer: Arc<Vec<u8>>, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= buffer.len());
+
+        Self {
+            buffer,
+            range,
+            owned: true,
+        }
+    }
+
+    /// Copies the data into a buffer of its own, unless it already has one.
+    pub fn into_owned(self) -> Self {
+        if self.owned {
+            self
+        } else {
+            self.to_vec().into()
+        }
+    }
+}
+
+impl From<Vec<u8>> for ChunkData {
+    fn from(data: Vec<u8>) -> Self {
+        let range = 0..data.len();
+        Self {
+            buffer: Arc::new(data),
+            range,
+            owned: true,
+        }
+    }
+}
+
+impl Deref for ChunkData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.buffer).as_ref()[self.range.clone()]
+    }
+}
+
+impl PartialEq for ChunkData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
 
 pub struct Chunker {
     name: &'static str,
@@ -36,7 +111,19 @@ pub enum ChunkerType {
 
 trait ChunkerImpl: Send + Sync {
     fn get_max_block_size(&self) -> u32;
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()>;
+
+    /// Files are split as any other reader, unless the chunker can do better with them (e.g. using mmap).
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        self.split_reader(&mut file, cb)
+    }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()>;
+
+    /// The size of the chunk that starts at the beginning of `data`, for chunkers where it only depends on the data
+    /// after the previous cut. These chunkers can split a file in parallel.
+    fn find_cut(&self, _data: &[u8]) -> Option<usize> {
+        None
+    }
 }
 
 impl Chunker {
@@ -77,16 +164,218 @@ impl Chunker {
         self.inner.get_max_block_size()
     }
 
-    pub fn split(&self, path: &path::Path, metadata: &fs::Metadata, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+    pub fn split(&self, path: &path::Path, metadata: &fs::Metadata, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if metadata.len() < self.get_block_size() as u64 {
             let chunk = fs::read(path)?;
-            cb(chunk);
+            if !chunk.is_empty() {
+                cb(chunk.into());
+            }
             return Ok(());
         }
 
         let file = fs::File::open(path)?;
         self.inner.split(file, cb)
     }
+
+    /// Splits data from any source, creating the same chunks `split` would for a file with the same contents.
+    pub fn split_reader(&self, mut reader: impl Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        // Like in split, data smaller than a block is a single chunk
+        let mut start = Vec::new();
+        (&mut reader)
+            .take(self.get_block_size() as u64)
+            .read_to_end(&mut start)?;
+
+        if start.len() < self.get_block_size() as usize {
+            if !start.is_empty() {
+                cb(start.into());
+            }
+            return Ok(());
+        }
+
+        self.inner
+            .split_reader(&mut Cursor::new(start).chain(reader), cb)
+    }
+
+    pub fn supports_parallel_split(&self) -> bool {
+        self.inner.find_cut(&[]).is_some()
+    }
+
+    /// Whether `split_parallel` uses more than one thread for a file of this size.
+    pub fn can_split_parallel(&self, len: u64) -> bool {
+        len >= 2 * PARALLEL_SEGMENT_SIZE as u64 && self.supports_parallel_split()
+    }
+
+    /// Splits a large file using up to `threads` threads, creating the same chunks `split` would. Chunks are passed to
+    /// `cb` in order, from the calling thread. The file is read instead of mmapped, see `split_file`.
+    pub fn split_parallel(
+        &self,
+        path: &path::Path,
+        metadata: &fs::Metadata,
+        threads: usize,
+        cb: &mut dyn FnMut(ChunkData),
+    ) -> Result<()> {
+        if !self.supports_parallel_split() {
+            return self.split(path, metadata, cb);
+        }
+
+        let mut file = fs::File::open(path)?;
+        self.split_file(&mut file, 0..metadata.len(), threads, cb)
+    }
+
+    /// Like `split_parallel`, but the holes of sparse files are passed to `hole_cb`, with their size, instead of being
+    /// read as zeros. The data around each hole is chunked on its own.
+    pub fn split_sparse(
+        &self,
+        path: &path::Path,
+        metadata: &fs::Metadata,
+        threads: usize,
+        cb: &mut dyn FnMut(ChunkData),
+        hole_cb: &mut dyn FnMut(u64),
+    ) -> Result<()> {
+        let len = metadata.len();
+
+        let mut file = fs::File::open(path)?;
+        let extents = match sparse::data_extents(&file, len)? {
+            // A single extent with all the data has no holes
+            Some(extents) if !matches!(extents.as_slice(), [all] if *all == (0..len)) => extents,
+            _ => return self.split_parallel(path, metadata, threads, cb),
+        };
+
+        let mut pos = 0;
+        for extent in extents {
+            if extent.start > pos {
+                hole_cb(extent.start - pos);
+            }
+
+            pos = extent.end;
+            if self.supports_parallel_split() {
+                self.split_file(&mut file, extent, threads, cb)?;
+            } else {
+                file.seek(SeekFrom::Start(extent.start))?;
+                self.split_reader((&mut file).take(extent.end - extent.start), cb)?;
+            }
+        }
+        if len > pos {
+            hole_cb(len - pos);
+        }
+
+        Ok(())
+    }
+
+    /// Splits a range of the file, creating the same chunks `split_reader` would for its data. The data is read in
+    /// segments, each into a buffer shared by the chunks that start in it, so their bytes are only copied when they are
+    /// packed. Unlike a mmap, the buffers don't change if the file does, so what is hashed is what is stored.
+    fn split_file(
+        &self,
+        file: &mut fs::File,
+        range: Range<u64>,
+        threads: usize,
+        cb: &mut dyn FnMut(ChunkData),
+    ) -> Result<()> {
+        let len = range.end - range.start;
+        let mut read = |start: usize, size: usize| -> Result<Vec<u8>> {
+            file.seek(SeekFrom::Start(range.start + start as u64))?;
+            let mut buffer = Vec::with_capacity(size);
+            (&mut *file).take(size as u64).read_to_end(&mut buffer)?;
+            Ok(buffer)
+        };
+
+        // Like in split, data smaller than a block is a single chunk
+        if len < self.get_block_size() as u64 {
+            let chunk = read(0, len as usize)?;
+            if !chunk.is_empty() {
+                cb(chunk.into());
+            }
+            return Ok(());
+        }
+
+        if self.can_split_parallel(len) {
+            self.split_segments(len as usize, threads, PARALLEL_SEGMENT_SIZE, &mut read, cb)
+        } else {
+            self.split_segments(len as usize, 1, READ_SEGMENT_SIZE, &mut read, cb)
+        }
+    }
+
+    /// Reads `len` bytes in segments with `read`, and chunks the segments of each batch of `threads` in parallel.
+    /// Each segment is read with the start of the next one, so the chunks that start in it also end in it, and is
+    /// chunked on its own thread as if a chunk started at its beginning. Then the cuts are followed from the start of
+    /// the data: once a cut falls on one of the cuts found for a segment, the rest of the segment is known to be
+    /// chunked the same way as sequentially. Until then, the cuts are found again.
+    fn split_segments(
+        &self,
+        len: usize,
+        threads: usize,
+        segment_size: usize,
+        read: &mut dyn FnMut(usize, usize) -> Result<Vec<u8>>,
+        cb: &mut dyn FnMut(ChunkData),
+    ) -> Result<()> {
+        let find_cut = |data: &[u8], start: usize| start + self.inner.find_cut(&data[start..]).unwrap();
+        let overlap = self.get_max_block_size() as usize;
+
+        let mut len = len;
+        let mut next = 0;
+        let mut pos = 0;
+        while next < len {
+            let mut batch = Vec::new();
+            while batch.len() < max(threads, 1) && next < len {
+                let size = min(segment_size + overlap, len - next);
+                let buffer = read(next, size)?;
+                // The data of a file that got shorter ends where it did. The change is found once it was read
+                if buffer.len() < size {
+                    len = next + buffer.len();
+                }
+                batch.push((next, Arc::new(buffer)));
+                next += segment_size;
+            }
+
+            let data_len = |start: usize, buffer: &Arc<Vec<u8>>| min(buffer.len(), len - start);
+
+            let segments_cuts: Vec<Vec<usize>> = std::thread::scope(|s| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|(start, buffer)| {
+                        let data = &buffer[..data_len(*start, buffer)];
+                        s.spawn(move || {
+                            let end = min(segment_size, data.len());
+
+                            let mut cuts = vec![0];
+                            let mut cut = 0;
+                            while cut < end {
+                                cut = find_cut(data, cut);
+                                cuts.push(cut);
+                            }
+                            cuts
+                        })
+                    })
+                    .collect();
+
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+
+            for ((start, buffer), cuts) in batch.iter().zip(segments_cuts) {
+                let data = &buffer[..data_len(*start, buffer)];
+                let end = start + min(segment_size, data.len());
+
+                while pos < end {
+                    match cuts.binary_search(&(pos - start)) {
+                        Ok(synced) => {
+                            for chunk in cuts[synced..].windows(2) {
+                                cb(ChunkData::shared(buffer.clone(), chunk[0]..chunk[1]));
+                            }
+                            pos = start + cuts.last().unwrap();
+                        }
+                        Err(_) => {
+                            let cut = find_cut(data, pos - start);
+                            cb(ChunkData::shared(buffer.clone(), pos - start..cut));
+                            pos = start + cut;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 type Factory = Box<dyn Fn(u32) -> Arc<Chunker> + Send + Sync>;
@@ -123,3 +412,174 @@ fn create_chunkers() -> HashMap<&'static str, Factory> {
 
     by_name
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::min;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::chunk::{ChunkData, Chunker};
+
+    /// Returns the data in small reads of varying sizes, like a pipe would.
+    struct TrickleReader {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for TrickleReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = min(buf.len(), min(self.data.len() - self.pos, 1 + self.pos % 5000));
+            buf[..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+            self.pos += size;
+            Ok(size)
+        }
+    }
+
+    #[test]
+    fn into_owned_drops_the_shared_buffer() {
+        let buffer = Arc::new(vec![1u8, 2, 3, 4]);
+
+        let chunk = ChunkData::new(buffer.clone(), 1..3).into_owned();
+        assert_eq!(1, Arc::strong_count(&buffer));
+        assert_eq!(&[2, 3], &*chunk);
+    }
+
+    #[test]
+    fn split_reader_creates_the_same_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("data");
+
+        let block_size = 16 * 1024;
+        let mut all = vec![0u8; 1_000_000];
+        StdRng::seed_from_u64(2).fill(&mut all[..]);
+
+        for size in [0, 1000, block_size - 1, block_size, block_size + 1, all.len()] {
+            let data = all[..size].to_vec();
+            std::fs::write(&path, &data).unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+
+            for name in Chunker::list_available_names() {
+                let chunker = Chunker::build_by_name(name, block_size as u32).unwrap();
+
+                let mut from_file = Vec::new();
+                chunker
+                    .split(&path, &metadata, &mut |c| from_file.push(c.to_vec()))
+                    .unwrap();
+
+                let mut from_reader = Vec::new();
+                let reader = TrickleReader {
+                    data: data.clone(),
+                    pos: 0,
+                };
+                chunker
+                    .split_reader(reader, &mut |c| from_reader.push(c.to_vec()))
+                    .unwrap();
+
+                assert!(from_file == from_reader, "{} with {} bytes", name, size);
+                assert_eq!(data, from_file.concat(), "{} with {} bytes", name, size);
+            }
+        }
+    }
+
+    #[test]
+    fn split_parallel_creates_the_same_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("data");
+
+        let mut data = vec![0u8; 3_000_000];
+        StdRng::seed_from_u64(1).fill(&mut data[..]);
+        std::fs::write(&path, &data).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        for name in Chunker::list_available_names() {
+            let chunker = Chunker::build_by_name(name, 16 * 1024).unwrap();
+            if !chunker.supports_parallel_split() {
+                continue;
+            }
+
+            let mut sequential = Vec::new();
+            chunker
+                .split(&path, &metadata, &mut |c| sequential.push(c))
+                .unwrap();
+
+            for (threads, segment_size) in [(2, 100_000), (3, 1_000_000), (8, 40_000)] {
+                let mut parallel = Vec::new();
+                chunker
+                    .split_segments(
+                        data.len(),
+                        threads,
+                        segment_size,
+                        &mut |start, size| Ok(data[start..start + size].to_vec()),
+                        &mut |c| parallel.push(c),
+                    )
+                    .unwrap();
+                assert!(sequential == parallel, "{} with {} threads", name, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn split_segments_stops_where_the_data_got_shorter() {
+        let mut data = vec![0u8; 1_000_000];
+        StdRng::seed_from_u64(4).fill(&mut data[..]);
+
+        let chunker = Chunker::build_by_name("Rabin64", 16 * 1024).unwrap();
+        let mut chunks = Vec::new();
+        chunker
+            .split_segments(
+                data.len(),
+                2,
+                100_000,
+                &mut |start, size| Ok(data[start..min(start + size, 450_000)].to_vec()),
+                &mut |c| chunks.push(c.to_vec()),
+            )
+            .unwrap();
+
+        assert_eq!(&data[..450_000], &chunks.concat()[..]);
+    }
+
+    /// Holes are only found on Linux.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn split_sparse_skips_holes() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("sparse");
+
+        let mut data = vec![0u8; 100_000];
+        StdRng::seed_from_u64(3).fill(&mut data[..]);
+
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.seek(SeekFrom::Start(10 << 20)).unwrap();
+        file.write_all(&data).unwrap();
+        file.set_len(30 << 20).unwrap();
+        drop(file);
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        assert!(
+            std::os::unix::fs::MetadataExt::blocks(&metadata) * 512 < metadata.len(),
+            "the temp dir must support sparse files"
+        );
+
+        let chunker = Chunker::build_by_name("FastCDC", 16 * 1024).unwrap();
+        let mut chunks = Vec::new();
+        let mut holes = 0;
+        chunker
+            .split_sparse(&path, &metadata, 4, &mut |c| chunks.push(c.to_vec()), &mut |size| holes += size)
+            .unwrap();
+
+        let read = chunks.concat();
+        assert_eq!(metadata.len(), read.len() as u64 + holes);
+        assert!(holes > 0);
+        assert!(read.len() < 1 << 20);
+        assert!(read.windows(data.len()).any(|w| w == &data[..]));
+    }
+}
@@ -21,19 +21,17 @@ impl Rabin {
         }
     }
 
-    fn split_mem(&self, mut file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+    fn split_mem(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         let mut buffer = Vec::with_capacity(self.block_max);
 
         let mut rabin = cdc::Rabin64::new(6);
 
         loop {
-            let read_len = (&mut file)
+            // At the end of the data, what is left in the buffer may still need to be split
+            reader
                 .take((self.block_max - buffer.len()) as u64)
                 .read_to_end(&mut buffer)?;
 
-            if read_len == 0 {
-                break;
-            }
             if buffer.len() <= self.block_min {
                 break;
             }
@@ -45,7 +43,7 @@ impl Rabin {
                 chunk_len += 1;
             }
 
-            cb(Vec::from(&buffer[..chunk_len]));
+            cb(Vec::from(&buffer[..chunk_len]).into());
 
             if chunk_len < buffer.len() {
                 buffer.copy_within(chunk_len.., 0);
@@ -54,14 +52,14 @@ impl Rabin {
         }
 
         if buffer.len() > 0 {
-            cb(buffer);
+            cb(buffer.into());
         }
 
         Ok(())
     }
 
-    fn split_mmap(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
-        let mmap = unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? };
+    fn split_mmap(&self, file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        let mmap = Arc::new(unsafe { memmap2::Mmap::map(&file).context("failed to mmap")? });
 
         let mut rabin = cdc::Rabin64::new(6);
 
@@ -76,12 +74,12 @@ impl Rabin {
                 chunk_len += 1;
             }
 
-            cb(Vec::from(&mmap[pos..pos + chunk_len]));
+            cb(ChunkData::new(mmap.clone(), pos..pos + chunk_len));
             pos += chunk_len;
         }
 
         if pos < mmap.len() {
-            cb(Vec::from(&mmap[pos..]));
+            cb(ChunkData::new(mmap.clone(), pos..mmap.len()));
         }
 
         Ok(())
@@ -93,12 +91,34 @@ impl ChunkerImpl for Rabin {
         self.block_max as u32
     }
 
-    fn split(&self, file: fs::File, cb: &mut dyn FnMut(Vec<u8>)) -> Result<()> {
+    fn split(&self, mut file: fs::File, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
         if self.mmap {
             self.split_mmap(file, cb)
         } else {
-            self.split_mem(file, cb)
+            self.split_mem(&mut file, cb)
+        }
+    }
+
+    fn split_reader(&self, reader: &mut dyn Read, cb: &mut dyn FnMut(ChunkData)) -> Result<()> {
+        self.split_mem(reader, cb)
+    }
+
+    fn find_cut(&self, data: &[u8]) -> Option<usize> {
+        if data.len() <= self.block_min {
+            return Some(data.len());
         }
+
+        let mut rabin = cdc::Rabin64::new(6);
+
+        let mut chunk_len = self.block_min;
+        rabin.reset_and_prefill_window(&mut data[chunk_len - 64..chunk_len].iter().copied());
+
+        while !predicate(rabin.hash) && chunk_len < self.block_max && chunk_len < data.len() {
+            rabin.slide(&data[chunk_len]);
+            chunk_len += 1;
+        }
+
+        Some(chunk_len)
     }
 }
 
@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+
+/// The ranges of a file that have data, in order. Everything else is a hole, that reads as zeros but is not stored.
+/// Returns `None` if the file system can't tell where the holes are.
+#[cfg(target_os = "linux")]
+pub fn data_extents(file: &File, len: u64) -> io::Result<Option<Vec<Range<u64>>>> {
+    use std::os::unix::io::AsRawFd;
+
+    let seek = |offset: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+        match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+            -1 => match io::Error::last_os_error() {
+                // No data after the offset
+                e if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                e => Err(e),
+            },
+            pos => Ok(Some(pos as u64)),
+        }
+    };
+
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        let start = match seek(pos, libc::SEEK_DATA) {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+            Err(e) => return Err(e),
+            Ok(None) => break,
+            Ok(Some(start)) => start,
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(len).min(len);
+        if start >= end {
+            break;
+        }
+
+        result.push(start..end);
+        pos = end;
+    }
+
+    Ok(Some(result))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn data_extents(_file: &File, _len: u64) -> io::Result<Option<Vec<Range<u64>>>> {
+    Ok(None)
+}
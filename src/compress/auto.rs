@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use super::*;
+
+/// zstd with a level that follows the pipeline: it goes up while storing packs is the bottleneck and down while
+/// compressing them is.
+pub struct AutoZstdCompressor {
+    level: AtomicI32,
+    min_level: i32,
+    max_level: i32,
+}
+
+impl AutoZstdCompressor {
+    pub fn new(min_level: i32, max_level: i32) -> Self {
+        AutoZstdCompressor {
+            level: AtomicI32::new(3.clamp(min_level, max_level)),
+            min_level,
+            max_level,
+        }
+    }
+}
+
+impl CompressorImpl for AutoZstdCompressor {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.compress_with_level(data)?.1)
+    }
+
+    fn decompress(&self, data: &[u8], _result_size: u32) -> Result<Vec<u8>> {
+        let result = ::zstd::stream::decode_all(data)?;
+        Ok(result)
+    }
+
+    fn get_level(&self) -> Option<i32> {
+        Some(self.level.load(Ordering::SeqCst))
+    }
+
+    fn compress_with_level(&self, data: &[u8]) -> Result<(Option<i32>, Vec<u8>)> {
+        let level = self.level.load(Ordering::SeqCst);
+        let result = ::zstd::stream::encode_all(data, level)?;
+        Ok((Some(level), result))
+    }
+
+    fn adapt(&self, bottleneck: Bottleneck) -> Option<i32> {
+        let delta = match bottleneck {
+            Bottleneck::Compression => -1,
+            Bottleneck::Storage => 1,
+        };
+
+        let old = self
+            .level
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |level| {
+                let new = (level + delta).clamp(self.min_level, self.max_level);
+                if new == level {
+                    None
+                } else {
+                    Some(new)
+                }
+            })
+            .ok()?;
+
+        Some(old + delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::auto::AutoZstdCompressor;
+    use crate::compress::{Bottleneck, CompressorImpl};
+
+    #[test]
+    fn adapt_stays_inside_limits() {
+        let c = AutoZstdCompressor::new(2, 4);
+        assert_eq!(Some(3), c.get_level());
+
+        assert_eq!(Some(4), c.adapt(Bottleneck::Storage));
+        assert_eq!(None, c.adapt(Bottleneck::Storage));
+        assert_eq!(Some(3), c.adapt(Bottleneck::Compression));
+        assert_eq!(Some(2), c.adapt(Bottleneck::Compression));
+        assert_eq!(None, c.adapt(Bottleneck::Compression));
+        assert_eq!(Some(2), c.get_level());
+
+        let data = vec![7u8; 10_000];
+        let (level, compressed) = c.compress_with_level(&data).unwrap();
+        assert_eq!(Some(2), level);
+        assert_eq!(data, c.decompress(&compressed, data.len() as u32).unwrap());
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 
+use crate::compress::auto::AutoZstdCompressor;
 use crate::compress::brotli::BrotliCompressor;
 use crate::compress::bzip2::Bzip2Compressor;
 use crate::compress::deflate::DeflateCompressor;
@@ -13,6 +14,7 @@ use crate::compress::snappy::SnappyCompressor;
 use crate::compress::zlib::ZlibCompressor;
 use crate::compress::zstd::ZstdCompressor;
 
+mod auto;
 mod brotli;
 mod bzip2;
 mod deflate;
@@ -43,9 +45,32 @@ pub enum CompressionType {
     LZ4,
 }
 
+/// Which side of the compression step is holding the pipeline back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bottleneck {
+    /// Packs are waiting to be compressed: compress faster.
+    Compression,
+    /// Compressed packs are waiting to be stored: there is CPU to spare for better compression.
+    Storage,
+}
+
 trait CompressorImpl: Send + Sync {
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
     fn decompress(&self, data: &[u8], result_size: u32) -> Result<Vec<u8>>;
+
+    fn get_level(&self) -> Option<i32> {
+        None
+    }
+
+    /// Compresses and returns the level used. Compressors that change their level must read it only once, so the
+    /// level returned is the one used.
+    fn compress_with_level(&self, data: &[u8]) -> Result<(Option<i32>, Vec<u8>)> {
+        Ok((self.get_level(), self.compress(data)?))
+    }
+
+    fn adapt(&self, _bottleneck: Bottleneck) -> Option<i32> {
+        None
+    }
 }
 
 impl Compressor {
@@ -59,21 +84,21 @@ impl Compressor {
     }
 
     pub fn build_by_name(name: &str) -> Result<Arc<Compressor>> {
-        let compressor = REGISTERED
+        let factory = REGISTERED
             .0
             .get(name)
             .with_context(|| format!("unknown compressor: '{}'", name))?;
 
-        Ok(compressor.clone())
+        Ok(factory())
     }
 
     pub fn build_by_type(ct: CompressionType) -> Result<Arc<Compressor>> {
-        let compressor = REGISTERED
+        let factory = REGISTERED
             .1
             .get(&ct)
             .with_context(|| format!("unknown compressor: {:?}", ct))?;
 
-        Ok(compressor.clone())
+        Ok(factory())
     }
 
     fn new(name: &'static str, ct: CompressionType, inner: Box<dyn CompressorImpl>) -> Self {
@@ -88,36 +113,57 @@ impl Compressor {
         self.ct
     }
 
-    pub fn compress(&self, data: Vec<u8>) -> Result<(CompressionType, Vec<u8>)> {
+    /// The level the next call to `compress` will use, if the compressor has a configurable one.
+    pub fn get_level(&self) -> Option<i32> {
+        self.inner.get_level()
+    }
+
+    /// Lets adaptive compressors react to the pipeline bottleneck. Returns the new level if it changed.
+    pub fn adapt(&self, bottleneck: Bottleneck) -> Option<i32> {
+        self.inner.adapt(bottleneck)
+    }
+
+    /// Returns the type and level used. The data is kept as is if compressing doesn't make it smaller.
+    pub fn compress(&self, data: Vec<u8>) -> Result<(CompressionType, Option<i32>, Vec<u8>)> {
         if self.ct == CompressionType::NONE {
-            return Ok((CompressionType::NONE, data));
+            return Ok((CompressionType::NONE, None, data));
         }
 
-        let result = self.inner.compress(&data)?;
+        let (level, result) = self.inner.compress_with_level(&data)?;
 
         if result.len() < data.len() {
-            Ok((self.ct, result))
+            Ok((self.ct, level, result))
         } else {
-            Ok((CompressionType::NONE, data))
+            Ok((CompressionType::NONE, None, data))
         }
     }
+
+    pub fn decompress(&self, data: Vec<u8>, result_size: u32) -> Result<Vec<u8>> {
+        if self.ct == CompressionType::NONE {
+            return Ok(data);
+        }
+
+        self.inner.decompress(&data, result_size)
+    }
 }
 
+type Factory = Box<dyn Fn() -> Arc<Compressor> + Send + Sync>;
+
 lazy_static! {
-    static ref REGISTERED: (HashMap<&'static str, Arc<Compressor>>, HashMap<CompressionType, Arc<Compressor>>) =
-        create_compressors();
+    static ref REGISTERED: (HashMap<&'static str, Factory>, HashMap<CompressionType, Factory>) = create_compressors();
 }
 
-fn create_compressors() -> (HashMap<&'static str, Arc<Compressor>>, HashMap<CompressionType, Arc<Compressor>>) {
+fn create_compressors() -> (HashMap<&'static str, Factory>, HashMap<CompressionType, Factory>) {
     let mut by_name = HashMap::new();
     let mut by_type = HashMap::new();
 
     macro_rules! register {
         ($n:expr, $t:expr,  $f:expr) => {
-            let c = Arc::new(Compressor::new($n, $t, Box::new($f)));
-            by_name.insert($n, c.clone());
+            let factory: Factory = Box::new(|| Arc::new(Compressor::new($n, $t, Box::new($f))));
+            by_name.insert($n, factory);
             if !by_type.contains_key(&$t) {
-                by_type.insert($t, c);
+                let factory: Factory = Box::new(|| Arc::new(Compressor::new($n, $t, Box::new($f))));
+                by_type.insert($t, factory);
             }
         };
     }
@@ -129,6 +175,7 @@ fn create_compressors() -> (HashMap<&'static str, Arc<Compressor>>, HashMap<Comp
     register!("zstd-default", ZSTD, ZstdCompressor::new(3));
     register!("zstd-fastest", ZSTD, ZstdCompressor::new(1));
     register!("zstd-better-compression", ZSTD, ZstdCompressor::new(8));
+    register!("zstd-auto", ZSTD, AutoZstdCompressor::new(1, 19));
     register!("deflate-default", DEFLATE, DeflateCompressor::new(6));
     register!("deflate-fastest", DEFLATE, DeflateCompressor::new(1));
     register!("deflate-better-compression", DEFLATE, DeflateCompressor::new(9));
@@ -1,6 +1,8 @@
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::db::open_db;
+use crate::path_walk::WalkFilters;
 use crate::workspace::SharedItem;
 use anyhow::Result;
 use itertools::Itertools;
@@ -44,14 +46,30 @@ impl SharedItemsDB {
 
         let conn = self.pool.get()?;
 
-        let mut stmt = conn.prepare("SELECT * FROM shared_folders WHERE path = ?")?;
+        let mut stmt = conn.prepare(
+            "SELECT id, path, skip_cache_dirs, max_size, min_age, max_age, owners FROM shared_folders WHERE path = ?",
+        )?;
         let mut rows = stmt.query([path.to_str()])?;
 
         while let Some(row) = rows.next()? {
             let id: Uuid = row.get(0)?;
             let path: String = row.get(1)?;
-
-            result.push(SharedItem::new(id, PathBuf::from(path)));
+            let owners: Option<String> = row.get(6)?;
+
+            let mut item = SharedItem::new(id, PathBuf::from(path));
+            item.filters = WalkFilters {
+                skip_cache_dirs: row.get(2)?,
+                max_size: row.get(3)?,
+                min_age: row.get::<_, Option<u64>>(4)?.map(Duration::from_secs),
+                max_age: row.get::<_, Option<u64>>(5)?.map(Duration::from_secs),
+                owners: owners
+                    .iter()
+                    .flat_map(|o| o.split(','))
+                    .map(|o| o.parse())
+                    .collect::<Result<_, _>>()?,
+            };
+
+            result.push(item);
         }
 
         Ok(result.into_iter().at_most_one()?)
@@ -67,6 +85,26 @@ impl SharedItemsDB {
 
         Ok(())
     }
+
+    pub fn update(&self, folder: &SharedItem) -> Result<()> {
+        let conn = self.pool.get()?;
+
+        let filters = &folder.filters;
+        let owners = filters.owners.iter().map(|o| o.to_string()).join(",");
+        conn.execute(
+            "UPDATE shared_folders SET skip_cache_dirs = ?, max_size = ?, min_age = ?, max_age = ?, owners = ? WHERE id = ?",
+            (
+                filters.skip_cache_dirs,
+                filters.max_size,
+                filters.min_age.map(|a| a.as_secs()),
+                filters.max_age.map(|a| a.as_secs()),
+                (!owners.is_empty()).then_some(owners),
+                &folder.id,
+            ),
+        )?;
+
+        Ok(())
+    }
 }
 
 mod embedded {
@@ -0,0 +1,398 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+
+use crate::chunk::ChunkData;
+use crate::pack::index::ChunkIndex;
+
+pub use sketch::Sketch;
+
+mod sketch;
+
+/// Length of the blocks used to find matches between base and target.
+const BLOCK_SIZE: usize = 32;
+/// Only every nth position of the base is indexed.
+const BLOCK_STEP: usize = 8;
+
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Encodes `target` as a list of copies from `base` and inserted literals.
+pub fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    write_varint(&mut result, target.len() as u64);
+
+    let mut blocks = HashMap::new();
+    if base.len() >= BLOCK_SIZE {
+        let mut hash = RollingHash::new(&base[..BLOCK_SIZE]);
+        for pos in 0..=base.len() - BLOCK_SIZE {
+            if pos > 0 {
+                hash.roll(base[pos - 1], base[pos + BLOCK_SIZE - 1]);
+            }
+            if pos % BLOCK_STEP == 0 {
+                blocks.entry(hash.get()).or_insert(pos);
+            }
+        }
+    }
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    if target.len() >= BLOCK_SIZE && !blocks.is_empty() {
+        let mut hash = RollingHash::new(&target[..BLOCK_SIZE]);
+        let mut hash_pos = 0;
+
+        while pos + BLOCK_SIZE <= target.len() {
+            if hash_pos != pos {
+                hash = RollingHash::new(&target[pos..pos + BLOCK_SIZE]);
+                hash_pos = pos;
+            }
+
+            let found = blocks
+                .get(&hash.get())
+                .copied()
+                .filter(|b| base[*b..*b + BLOCK_SIZE] == target[pos..pos + BLOCK_SIZE]);
+
+            if let Some(base_pos) = found {
+                let mut start = pos;
+                let mut base_start = base_pos;
+                while start > literal_start && base_start > 0 && target[start - 1] == base[base_start - 1] {
+                    start -= 1;
+                    base_start -= 1;
+                }
+
+                let mut end = pos + BLOCK_SIZE;
+                let mut base_end = base_pos + BLOCK_SIZE;
+                while end < target.len() && base_end < base.len() && target[end] == base[base_end] {
+                    end += 1;
+                    base_end += 1;
+                }
+
+                write_insert(&mut result, &target[literal_start..start]);
+                write_copy(&mut result, base_start, end - start);
+
+                literal_start = end;
+                pos = end;
+            } else {
+                if pos + BLOCK_SIZE < target.len() {
+                    hash.roll(target[pos], target[pos + BLOCK_SIZE]);
+                    hash_pos += 1;
+                }
+                pos += 1;
+            }
+        }
+    }
+
+    write_insert(&mut result, &target[literal_start..]);
+
+    result
+}
+
+/// Rebuilds the target from the `base` and a delta created by `encode`.
+pub fn decode(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = delta;
+
+    let size = read_varint(&mut reader)? as usize;
+    let mut result = Vec::with_capacity(size);
+
+    while !reader.is_empty() {
+        let op = reader[0];
+        reader = &reader[1..];
+
+        match op {
+            OP_COPY => {
+                let start = read_varint(&mut reader)? as usize;
+                let len = read_varint(&mut reader)? as usize;
+                let copy = base
+                    .get(start..start + len)
+                    .ok_or_else(|| Error::msg("invalid delta: copy outside of base"))?;
+                result.extend_from_slice(copy);
+            }
+            OP_INSERT => {
+                let len = read_varint(&mut reader)? as usize;
+                anyhow::ensure!(len <= reader.len(), "invalid delta: insert after end of data");
+                result.extend_from_slice(&reader[..len]);
+                reader = &reader[len..];
+            }
+            _ => return Err(Error::msg(format!("invalid delta: unknown operation {}", op))),
+        }
+    }
+
+    anyhow::ensure!(result.len() == size, "invalid delta: expected {} bytes, got {}", size, result.len());
+
+    Ok(result)
+}
+
+fn write_copy(out: &mut Vec<u8>, start: usize, len: usize) {
+    out.push(OP_COPY);
+    write_varint(out, start as u64);
+    write_varint(out, len as u64);
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+
+    out.push(OP_INSERT);
+    write_varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut result = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let (b, rest) = data
+            .split_first()
+            .ok_or_else(|| Error::msg("invalid delta: truncated number"))?;
+        *data = rest;
+
+        anyhow::ensure!(shift < 64, "invalid delta: number too big");
+        result |= ((b & 0x7F) as u64) << shift;
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+}
+
+const HASH_BASE: u64 = 0x100000001B3;
+
+struct RollingHash {
+    hash: u64,
+    out_factor: u64,
+}
+
+impl RollingHash {
+    fn new(data: &[u8]) -> Self {
+        let mut hash = 0u64;
+        let mut out_factor = 1u64;
+        for (i, b) in data.iter().enumerate() {
+            hash = hash.wrapping_mul(HASH_BASE).wrapping_add(*b as u64 + 1);
+            if i > 0 {
+                out_factor = out_factor.wrapping_mul(HASH_BASE);
+            }
+        }
+
+        Self { hash, out_factor }
+    }
+
+    fn roll(&mut self, out: u8, ins: u8) {
+        self.hash = self
+            .hash
+            .wrapping_sub((out as u64 + 1).wrapping_mul(self.out_factor))
+            .wrapping_mul(HASH_BASE)
+            .wrapping_add(ins as u64 + 1);
+    }
+
+    fn get(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// Maximum size of a delta, relative to the size of the chunk, for it to be worth storing.
+const MAX_DELTA_RATIO: f32 = 0.5;
+
+/// Stores chunks as deltas against similar chunks seen before. Only the data of recent chunks is kept in memory, so
+/// only those can be used as bases.
+pub struct DeltaCompressor {
+    index: Arc<ChunkIndex>,
+    recent: RecentChunks,
+}
+
+impl DeltaCompressor {
+    pub fn new(index: Arc<ChunkIndex>, cache_size: usize) -> Self {
+        Self {
+            index,
+            recent: RecentChunks::new(cache_size),
+        }
+    }
+
+    /// Returns the data to store and, if it is a delta, the hash of the base chunk. Chunks already in the index use
+    /// the base of their entry, and are stored whole if it is not in memory anymore.
+    pub fn compress(&mut self, hash: &[u8], data: ChunkData) -> (Option<Vec<u8>>, ChunkData) {
+        if let Some(entry) = self.index.get(hash) {
+            return match entry
+                .base
+                .and_then(|b| self.recent.get(&b).map(|base| (b, base)))
+            {
+                Some((base_hash, base)) => (Some(base_hash), encode(&base, &data).into()),
+                None => (None, data),
+            };
+        }
+
+        let sketch = Sketch::compute(&data);
+
+        let base = self
+            .index
+            .find_similar(&sketch)
+            .filter(|base_hash| base_hash != hash)
+            .and_then(|base_hash| self.recent.get(&base_hash).map(|base| (base_hash, base)));
+
+        if let Some((base_hash, base)) = base {
+            let delta = encode(&base, &data);
+
+            if (delta.len() as f32) < data.len() as f32 * MAX_DELTA_RATIO {
+                self.index
+                    .add(hash, data.len() as u32, Some(base_hash.clone()));
+                return (Some(base_hash), delta.into());
+            }
+        }
+
+        self.index.add(hash, data.len() as u32, None);
+        self.index.add_sketch(hash, &sketch);
+        self.recent.add(hash, data.clone());
+
+        (None, data)
+    }
+}
+
+struct RecentChunks {
+    max_size: usize,
+    size: usize,
+    order: VecDeque<Vec<u8>>,
+    chunks: HashMap<Vec<u8>, ChunkData>,
+}
+
+impl RecentChunks {
+    fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            size: 0,
+            order: VecDeque::new(),
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn get(&self, hash: &[u8]) -> Option<ChunkData> {
+        self.chunks.get(hash).cloned()
+    }
+
+    /// Chunks are kept long after their file was read, so they can't point into a mmap of it.
+    fn add(&mut self, hash: &[u8], data: ChunkData) {
+        let data = data.into_owned();
+        self.size += data.len();
+        self.order.push_back(hash.to_vec());
+        self.chunks.insert(hash.to_vec(), data);
+
+        while self.size > self.max_size {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(data) = self.chunks.remove(&oldest) {
+                self.size -= data.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::delta::{decode, encode, DeltaCompressor, Sketch};
+    use crate::pack::index::ChunkIndex;
+
+    fn random_data(seed: u64, size: usize) -> Vec<u8> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..size).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn round_trips_small_changes() {
+        let base = random_data(1, 100_000);
+
+        let mut target = base.clone();
+        target[10] ^= 0xFF;
+        target[50_000..50_010].fill(0);
+        target.splice(70_000..70_000, [1, 2, 3, 4, 5]);
+        target.drain(90_000..90_100);
+
+        let delta = encode(&base, &target);
+        assert!(delta.len() < 1000, "delta too big: {}", delta.len());
+
+        assert_eq!(target, decode(&base, &delta).unwrap());
+    }
+
+    #[test]
+    fn round_trips_unrelated_data() {
+        for size in [0, 1, 31, 32, 33, 1000] {
+            let base = random_data(2, size);
+            let target = random_data(3, size);
+
+            assert_eq!(target, decode(&base, &encode(&base, &target)).unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_delta() {
+        let base = random_data(4, 1000);
+        let target = random_data(5, 1000);
+
+        let mut delta = encode(&base, &target);
+        delta.truncate(delta.len() - 1);
+
+        assert!(decode(&base, &delta).is_err());
+    }
+
+    #[test]
+    fn stores_indexed_chunks_like_their_entry() {
+        let index = ChunkIndex::new();
+        let mut compressor = DeltaCompressor::new(index.clone(), 1024 * 1024);
+
+        let base = random_data(8, 100_000);
+        let mut similar = base.clone();
+        similar[500] ^= 0xFF;
+        let mut delta = base.clone();
+        delta[600] ^= 0xFF;
+
+        index.add(b"similar", similar.len() as u32, None);
+        assert!(compressor.compress(b"base", base.into()).0.is_none());
+        assert_eq!(Some(b"base".to_vec()), compressor.compress(b"delta", delta.clone().into()).0);
+
+        // Similar to the base, but already indexed as a whole chunk
+        let (base_hash, data) = compressor.compress(b"similar", similar.clone().into());
+        assert_eq!((None, &similar[..]), (base_hash, &data[..]));
+        assert_eq!(None, index.get(b"similar").unwrap().base);
+
+        let (base_hash, data) = compressor.compress(b"delta", delta.clone().into());
+        assert_eq!(Some(b"base".to_vec()), base_hash);
+        assert_eq!(index.get(b"delta").unwrap().base, base_hash);
+        assert_eq!(delta, decode(&random_data(8, 100_000), &data).unwrap());
+    }
+
+    #[test]
+    fn sketch_matches_similar_chunks() {
+        let base = random_data(6, 100_000);
+
+        let mut similar = base.clone();
+        similar[30_000] ^= 0xFF;
+
+        let base_sketch = Sketch::compute(&base);
+        let similar_sketch = Sketch::compute(&similar);
+        let other_sketch = Sketch::compute(&random_data(7, 100_000));
+
+        let matches = |a: &Sketch, b: &Sketch| {
+            a.super_features
+                .iter()
+                .zip(b.super_features.iter())
+                .filter(|(x, y)| x == y)
+                .count()
+        };
+
+        assert!(matches(&base_sketch, &similar_sketch) > 0);
+        assert_eq!(0, matches(&base_sketch, &other_sketch));
+    }
+}
@@ -0,0 +1,68 @@
+const FEATURES: usize = 12;
+const FEATURES_PER_SUPER_FEATURE: usize = 4;
+pub const SUPER_FEATURES: usize = FEATURES / FEATURES_PER_SUPER_FEATURE;
+
+/// Resemblance sketch of a chunk: super-features built from the maximum of several transformations of a rolling
+/// hash. Chunks that share a super-feature are very likely to share most of their content.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sketch {
+    pub super_features: [u64; SUPER_FEATURES],
+}
+
+impl Sketch {
+    pub fn compute(data: &[u8]) -> Sketch {
+        let mut features = [0u64; FEATURES];
+
+        let mut h: u64 = 0;
+        for b in data {
+            h = (h << 1).wrapping_add(GEAR[*b as usize]);
+
+            for (i, feature) in features.iter_mut().enumerate() {
+                let f = h
+                    .wrapping_mul(TRANSFORMS[i].0)
+                    .wrapping_add(TRANSFORMS[i].1);
+                if f > *feature {
+                    *feature = f;
+                }
+            }
+        }
+
+        let mut super_features = [0u64; SUPER_FEATURES];
+        for (i, sf) in super_features.iter_mut().enumerate() {
+            let mut v = i as u64;
+            for f in &features[i * FEATURES_PER_SUPER_FEATURE..(i + 1) * FEATURES_PER_SUPER_FEATURE] {
+                v = mix(v ^ f);
+            }
+            *sf = v;
+        }
+
+        Sketch { super_features }
+    }
+}
+
+const fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+const GEAR: [u64; 256] = {
+    let mut result = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        result[i] = mix(i as u64);
+        i += 1;
+    }
+    result
+};
+
+const TRANSFORMS: [(u64, u64); FEATURES] = {
+    let mut result = [(0u64, 0u64); FEATURES];
+    let mut i = 0;
+    while i < FEATURES {
+        result[i] = (mix(1000 + i as u64) | 1, mix(2000 + i as u64));
+        i += 1;
+    }
+    result
+};
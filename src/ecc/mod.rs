@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
 
+mod reed_solomon;
 mod secded;
 
 pub struct ECC {
-    name: &'static str,
+    name: String,
     et: ECCType,
     inner: Box<dyn ECCImpl>,
 }
@@ -16,11 +17,46 @@ pub struct ECC {
 pub enum ECCType {
     NONE,
     SECDED,
+    ReedSolomon,
+}
+
+/// What was found while reading data protected by ECC.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ECCReport {
+    pub blocks: u64,
+    /// Blocks that had errors that were corrected.
+    pub corrected: u64,
+    /// Blocks that had too many errors to be corrected.
+    pub uncorrectable: u64,
+}
+
+impl ECCReport {
+    pub fn add(&mut self, other: &ECCReport) {
+        self.blocks += other.blocks;
+        self.corrected += other.corrected;
+        self.uncorrectable += other.uncorrectable;
+    }
 }
 
 trait ECCImpl: Send + Sync {
     fn write(&self, data: Vec<u8>) -> Result<Vec<u8>>;
-    fn read(&self, data: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Reads the data, correcting what is possible. If some blocks are uncorrectable the result contains garbage in
+    /// their place.
+    fn read_with_report(&self, data: Vec<u8>) -> Result<(Vec<u8>, ECCReport)>;
+
+    fn read(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let (result, report) = self.read_with_report(data)?;
+
+        anyhow::ensure!(
+            report.uncorrectable == 0,
+            "Can't read data: Too many errors detected in {} of {} blocks",
+            report.uncorrectable,
+            report.blocks
+        );
+
+        Ok(result)
+    }
 }
 
 impl ECC {
@@ -32,20 +68,44 @@ impl ECC {
             .collect();
     }
 
+    /// Also builds the custom Reed-Solomon ECCs, from the names created by `build_reed_solomon`.
     pub fn build_by_name(name: &str) -> Result<Arc<ECC>> {
-        let factory = REGISTERED
-            .get(name)
-            .with_context(|| format!("unknown encryptor: '{}'", name))?;
+        if let Some(factory) = REGISTERED.get(name) {
+            return Ok(factory());
+        }
+
+        match parse_reed_solomon_name(name) {
+            Some((data_shards, parity_shards, shard_size, interleave)) => {
+                Self::build_reed_solomon(data_shards, parity_shards, shard_size, interleave)
+            }
+            None => Err(Error::msg(format!("unknown ECC: '{}'", name))),
+        }
+    }
 
-        Ok(factory())
+    /// Builds a Reed-Solomon ECC with custom parameters. See `reed_solomon::Impl` for their meaning. They are kept in
+    /// the name, like `Reed-Solomon (16+4, 4096, 8)`, so packs record them.
+    pub fn build_reed_solomon(
+        data_shards: usize,
+        parity_shards: usize,
+        shard_size: usize,
+        interleave: usize,
+    ) -> Result<Arc<ECC>> {
+        let inner = reed_solomon::Impl::new(data_shards, parity_shards, shard_size, interleave)?;
+        let name = format!("Reed-Solomon ({}+{}, {}, {})", data_shards, parity_shards, shard_size, interleave);
+
+        Ok(Arc::new(ECC::new(name, ECCType::ReedSolomon, Box::new(inner))))
     }
 
-    fn new(name: &'static str, et: ECCType, inner: Box<dyn ECCImpl>) -> Self {
-        Self { name, et, inner }
+    fn new(name: impl Into<String>, et: ECCType, inner: Box<dyn ECCImpl>) -> Self {
+        Self {
+            name: name.into(),
+            et,
+            inner,
+        }
     }
 
-    pub fn get_name(&self) -> &'static str {
-        self.name
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
 
     pub fn get_type(&self) -> ECCType {
@@ -58,8 +118,30 @@ impl ECC {
     }
 
     pub fn read(&self, data: Vec<u8>) -> Result<Vec<u8>> {
-        self.inner.read(data)
+        self.inner
+            .read(data)
+            .with_context(|| format!("error reading {}", self.name))
+    }
+
+    /// Reads the data and reports how many blocks had errors, instead of failing on uncorrectable ones.
+    pub fn read_with_report(&self, data: Vec<u8>) -> Result<(Vec<u8>, ECCReport)> {
+        self.inner.read_with_report(data)
+    }
+}
+
+/// Parses the parameters in `Reed-Solomon (<data>+<parity>, <shard size>, <interleave>)`.
+fn parse_reed_solomon_name(name: &str) -> Option<(usize, usize, usize, usize)> {
+    let params = name.strip_prefix("Reed-Solomon (")?.strip_suffix(')')?;
+
+    let mut fields = params.split(", ");
+    let (data_shards, parity_shards) = fields.next()?.split_once('+')?;
+    let shard_size = fields.next()?;
+    let interleave = fields.next()?;
+    if fields.next().is_some() {
+        return None;
     }
+
+    Some((data_shards.parse().ok()?, parity_shards.parse().ok()?, shard_size.parse().ok()?, interleave.parse().ok()?))
 }
 
 type Factory = Box<dyn Fn() -> Arc<ECC> + Send + Sync>;
@@ -82,6 +164,9 @@ fn create_encryptors() -> HashMap<&'static str, Factory> {
 
     register!("None", NONE, NoneECC::new());
     register!("SECDED", SECDED, secded::Impl::new());
+    register!("Reed-Solomon", ReedSolomon, reed_solomon::Impl::new(16, 4, 4096, 8).unwrap());
+    register!("Reed-Solomon (light)", ReedSolomon, reed_solomon::Impl::new(32, 2, 4096, 4).unwrap());
+    register!("Reed-Solomon (strong)", ReedSolomon, reed_solomon::Impl::new(8, 4, 4096, 16).unwrap());
 
     by_name
 }
@@ -99,7 +184,7 @@ impl ECCImpl for NoneECC {
         Ok(data)
     }
 
-    fn read(&self, data: Vec<u8>) -> Result<Vec<u8>> {
-        Ok(data)
+    fn read_with_report(&self, data: Vec<u8>) -> Result<(Vec<u8>, ECCReport)> {
+        Ok((data, ECCReport::default()))
     }
 }
@@ -0,0 +1,335 @@
+use std::cmp::min;
+
+use anyhow::{Context, Error, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use super::*;
+
+const MAGIC: &[u8; 4] = b"mfRS";
+const HEADER_SIZE: usize = 12;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Reed-Solomon erasure coding over shards protected by a CRC32.
+///
+/// The data is split in stripes of `data_shards` shards of `shard_size` bytes, each completed with `parity_shards`
+/// parity shards. A shard with a wrong checksum, or that is missing because the data was truncated, is treated as an
+/// erasure, so each stripe survives the loss of up to `parity_shards` shards. The shards of `interleave` consecutive
+/// stripes are written round-robin, so that a burst of errors spreads over several stripes.
+///
+/// Layout: header, shards, header. The header holds the number of stripes and is only needed if the data was
+/// truncated.
+pub struct Impl {
+    rs: ReedSolomon,
+    data_shards: usize,
+    parity_shards: usize,
+    shard_size: usize,
+    interleave: usize,
+}
+
+impl Impl {
+    pub fn new(data_shards: usize, parity_shards: usize, shard_size: usize, interleave: usize) -> Result<Self> {
+        anyhow::ensure!(shard_size > 0, "shard size must be positive");
+        anyhow::ensure!(interleave > 0, "interleave must be positive");
+
+        let rs = ReedSolomon::new(data_shards, parity_shards).map_err(|e| Error::msg(format!("{:?}", e)))?;
+
+        Ok(Self {
+            rs,
+            data_shards,
+            parity_shards,
+            shard_size,
+            interleave,
+        })
+    }
+
+    fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    fn stored_shard_size(&self) -> usize {
+        self.shard_size + CHECKSUM_SIZE
+    }
+
+    fn checksum(&self, stripe: usize, shard: usize, data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&(stripe as u64).to_le_bytes());
+        hasher.update(&(shard as u32).to_le_bytes());
+        hasher.update(data);
+        hasher.finalize().to_le_bytes()
+    }
+
+    fn write_header(&self, result: &mut Vec<u8>, stripes: usize) {
+        let start = result.len();
+        result.extend_from_slice(MAGIC);
+        result.extend_from_slice(&(stripes as u32).to_le_bytes());
+        let crc = crc32fast::hash(&result[start..]);
+        result.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    fn read_header(&self, header: &[u8]) -> Option<usize> {
+        if header.len() != HEADER_SIZE || &header[..4] != MAGIC {
+            return None;
+        }
+
+        let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if crc32fast::hash(&header[..8]) != crc {
+            return None;
+        }
+
+        Some(u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize)
+    }
+
+    fn read_stripe_count(&self, data: &[u8]) -> Result<usize> {
+        if let Some(stripes) = data.get(..HEADER_SIZE).and_then(|h| self.read_header(h)) {
+            return Ok(stripes);
+        }
+
+        if data.len() >= HEADER_SIZE {
+            if let Some(stripes) = self.read_header(&data[data.len() - HEADER_SIZE..]) {
+                return Ok(stripes);
+            }
+        }
+
+        // Both headers are damaged, but if the size is right the data was not truncated
+        let shards_size = data.len().saturating_sub(2 * HEADER_SIZE);
+        let stripe_size = self.total_shards() * self.stored_shard_size();
+        anyhow::ensure!(
+            shards_size > 0 && shards_size.is_multiple_of(stripe_size),
+            "Can't read data: headers are damaged and data was truncated (Reed-Solomon)"
+        );
+
+        Ok(shards_size / stripe_size)
+    }
+}
+
+impl ECCImpl for Impl {
+    fn write(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let stripe_data_size = self.data_shards * self.shard_size;
+
+        let mut payload = Vec::with_capacity(8 + data.len());
+        payload.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        payload.extend_from_slice(&data);
+        drop(data);
+
+        let stripes = payload.len().div_ceil(stripe_data_size);
+        payload.resize(stripes * stripe_data_size, 0);
+
+        let mut result = Vec::with_capacity(2 * HEADER_SIZE + stripes * self.total_shards() * self.stored_shard_size());
+
+        self.write_header(&mut result, stripes);
+
+        for group_start in (0..stripes).step_by(self.interleave) {
+            let group_size = min(self.interleave, stripes - group_start);
+
+            let mut group = Vec::with_capacity(group_size);
+            for stripe in group_start..group_start + group_size {
+                let stripe_data = &payload[stripe * stripe_data_size..(stripe + 1) * stripe_data_size];
+
+                let mut shards: Vec<Vec<u8>> = stripe_data.chunks(self.shard_size).map(Vec::from).collect();
+                shards.resize(self.total_shards(), vec![0; self.shard_size]);
+
+                self.rs
+                    .encode(&mut shards)
+                    .map_err(|e| Error::msg(format!("Reed-Solomon encoding failed: {:?}", e)))?;
+
+                group.push(shards);
+            }
+
+            for shard in 0..self.total_shards() {
+                for (i, shards) in group.iter().enumerate() {
+                    result.extend_from_slice(&shards[shard]);
+                    result.extend_from_slice(&self.checksum(group_start + i, shard, &shards[shard]));
+                }
+            }
+        }
+
+        self.write_header(&mut result, stripes);
+
+        Ok(result)
+    }
+
+    fn read_with_report(&self, data: Vec<u8>) -> Result<(Vec<u8>, ECCReport)> {
+        let mut report = ECCReport::default();
+
+        let stripes = self.read_stripe_count(&data)?;
+
+        let headers = [
+            data.get(..HEADER_SIZE),
+            data.len()
+                .checked_sub(HEADER_SIZE)
+                .map(|start| &data[start..]),
+        ];
+        for header in headers {
+            report.blocks += 1;
+            if header.and_then(|h| self.read_header(h)) != Some(stripes) {
+                report.corrected += 1;
+            }
+        }
+
+        let stored_shard_size = self.stored_shard_size();
+        let shards_data = data.get(HEADER_SIZE..).unwrap_or_default();
+
+        let mut payload = Vec::with_capacity(stripes * self.data_shards * self.shard_size);
+
+        for group_start in (0..stripes).step_by(self.interleave) {
+            let group_size = min(self.interleave, stripes - group_start);
+            let group_offset = group_start * self.total_shards() * stored_shard_size;
+
+            for i in 0..group_size {
+                let stripe = group_start + i;
+
+                let mut shards: Vec<Option<Vec<u8>>> = (0..self.total_shards())
+                    .map(|shard| {
+                        let start = group_offset + (shard * group_size + i) * stored_shard_size;
+                        let stored = shards_data.get(start..start + stored_shard_size)?;
+                        let (shard_data, checksum) = stored.split_at(self.shard_size);
+
+                        if checksum == self.checksum(stripe, shard, shard_data) {
+                            Some(Vec::from(shard_data))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+
+                let damaged = shards.iter().filter(|s| s.is_none()).count() as u64;
+                report.blocks += self.total_shards() as u64;
+
+                if damaged > 0 {
+                    if self.rs.reconstruct_data(&mut shards).is_ok() {
+                        report.corrected += damaged;
+                    } else {
+                        anyhow::ensure!(
+                            stripe > 0,
+                            "Can't read data: Too many errors detected in stripe 0 (Reed-Solomon)"
+                        );
+                        report.uncorrectable += damaged;
+                    }
+                }
+
+                for shard in shards.into_iter().take(self.data_shards) {
+                    payload.extend_from_slice(&shard.unwrap_or_else(|| vec![0; self.shard_size]));
+                }
+            }
+        }
+
+        let len = payload
+            .get(..8)
+            .context("Can't read data: no data found (Reed-Solomon)")?;
+        let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+        anyhow::ensure!(len <= payload.len() - 8, "Can't read data: invalid length (Reed-Solomon)");
+
+        payload.truncate(8 + len);
+        payload.drain(..8);
+
+        Ok((payload, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecc::reed_solomon::Impl;
+    use crate::ecc::{ECCImpl, ECCReport, ECC};
+
+    fn data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let i = Impl::new(4, 2, 64, 3).unwrap();
+
+        for size in [0, 1, 100, 255, 256, 1000, 5000] {
+            let orig = data(size);
+            let back = i.read(i.write(orig.clone()).unwrap()).unwrap();
+            assert_eq!(orig, back);
+        }
+    }
+
+    #[test]
+    fn rebuilds_custom_parameters_from_the_name() {
+        let ecc = ECC::build_reed_solomon(6, 3, 512, 2).unwrap();
+        assert_eq!("Reed-Solomon (6+3, 512, 2)", ecc.get_name());
+
+        let orig = data(10_000);
+        let (_, written) = ecc.write(orig.clone()).unwrap();
+        let rebuilt = ECC::build_by_name(ecc.get_name()).unwrap();
+        assert_eq!(orig, rebuilt.read(written).unwrap());
+
+        assert!(ECC::build_by_name("Reed-Solomon (6+3, 512)").is_err());
+    }
+
+    #[test]
+    fn recovers_zeroed_4k_regions() {
+        let i = Impl::new(16, 4, 4096, 8).unwrap();
+        let orig = data(3 * 1024 * 1024);
+        let ecc = i.write(orig.clone()).unwrap();
+
+        for start in [0, 1, 4095, 12345, 1024 * 1024 + 17, ecc.len() - 4096] {
+            let mut damaged = ecc.clone();
+            damaged[start..start + 4096].fill(0);
+            assert_eq!(orig, i.read(damaged).unwrap(), "zeroed at {}", start);
+        }
+
+        let mut damaged = ecc.clone();
+        for start in (0..ecc.len() - 4096).step_by(200 * 1024) {
+            damaged[start..start + 4096].fill(0);
+        }
+        assert_eq!(orig, i.read(damaged).unwrap());
+    }
+
+    #[test]
+    fn recovers_truncated_data() {
+        let i = Impl::new(16, 4, 4096, 8).unwrap();
+        let orig = data(1024 * 1024);
+        let mut ecc = i.write(orig.clone()).unwrap();
+
+        ecc.truncate(ecc.len() - 4096);
+        assert_eq!(orig, i.read(ecc).unwrap());
+    }
+
+    #[test]
+    fn fails_with_too_many_errors() {
+        let i = Impl::new(4, 2, 64, 1).unwrap();
+        let orig = data(1000);
+        let mut ecc = i.write(orig).unwrap();
+
+        ecc[12..12 + 3 * 68].fill(0);
+        assert!(i.read(ecc).is_err());
+    }
+
+    #[test]
+    fn reports_corrected_and_uncorrectable_shards() {
+        let i = Impl::new(4, 2, 64, 1).unwrap();
+        let orig = data(1000);
+        let ecc = i.write(orig.clone()).unwrap();
+        let shard = |stripe: usize, shard: usize| 12 + (stripe * 6 + shard) * 68;
+
+        let mut damaged = ecc.clone();
+        damaged[shard(1, 0) + 5] ^= 0x10;
+        damaged[shard(2, 3) + 5] ^= 0x10;
+        let (back, report) = i.read_with_report(damaged).unwrap();
+        assert_eq!(orig, back);
+        assert_eq!(
+            ECCReport {
+                blocks: 2 + 4 * 6,
+                corrected: 2,
+                uncorrectable: 0
+            },
+            report
+        );
+
+        let mut damaged = ecc.clone();
+        damaged[shard(2, 0)..shard(2, 3)].fill(0);
+        let (_, report) = i.read_with_report(damaged.clone()).unwrap();
+        assert_eq!(
+            ECCReport {
+                blocks: 2 + 4 * 6,
+                corrected: 0,
+                uncorrectable: 3
+            },
+            report
+        );
+        assert!(i.read(damaged).is_err());
+    }
+}
@@ -47,21 +47,26 @@ impl Impl {
         let mut buffer = data.to_be_bytes();
         self.sd.encode(&mut buffer);
 
-        let result = u64::from_be_bytes(buffer) << 1;
+        let result = u64::from_be_bytes(buffer);
 
         result
     }
 
-    fn decode(&self, mut data: u64) -> Result<u64> {
-        data = data >> 1;
+    fn decode(&self, raw: u64, report: &mut ECCReport) -> Result<u64> {
+        report.blocks += 1;
 
-        let mut buffer = data.to_be_bytes();
-        self.sd
-            .decode(&mut buffer)
-            .map_err(|_| Error::msg("Can't read data: Too many errors detected (SECDED)"))?;
+        let mut buffer = raw.to_be_bytes();
+        if self.sd.decode(&mut buffer).is_err() {
+            report.uncorrectable += 1;
+            return Err(Error::msg("Can't read data: Too many errors detected (SECDED)"));
+        }
 
         let result = u64::from_be_bytes(buffer) >> self.code_block_bits;
 
+        if self.encode(result) != raw {
+            report.corrected += 1;
+        }
+
         Ok(result)
     }
 }
@@ -89,7 +94,9 @@ impl ECCImpl for Impl {
         Ok(result)
     }
 
-    fn read(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+    fn read_with_report(&self, data: Vec<u8>) -> Result<(Vec<u8>, ECCReport)> {
+        let mut report = ECCReport::default();
+
         let mut data_reader = BitReader::new(&data);
 
         let mut result = Vec::with_capacity(self.get_input_size_bytes(data.len()));
@@ -98,7 +105,7 @@ impl ECCImpl for Impl {
         let (mut v, size) = data_reader.read_u64(self.output_block_bits)?;
         anyhow::ensure!(size > 32, "input too small");
 
-        v = self.decode(v)?;
+        v = self.decode(v, &mut report)?;
         let result_len = v >> (32 - self.code_block_bits);
         result_writer.write_u64(v, self.input_block_bits - 32)?;
 
@@ -108,12 +115,21 @@ impl ECCImpl for Impl {
                 break;
             }
 
-            result_writer.write_u64(self.decode(v)?, self.input_block_bits)?;
+            let decoded = self
+                .decode(v, &mut report)
+                .unwrap_or(v >> self.code_block_bits);
+            result_writer.write_u64(decoded, self.input_block_bits)?;
         }
 
+        // Blocks missing because the data was truncated
+        let missing_bits = (result_len as usize * 8).saturating_sub(result.len() * 8);
+        let missing = missing_bits.div_ceil(self.input_block_bits) as u64;
+        report.blocks += missing;
+        report.uncorrectable += missing;
+
         result.resize(result_len as usize, 0);
 
-        Ok(result)
+        Ok((result, report))
     }
 }
 
@@ -187,6 +203,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn reports_corrected_blocks() {
+        let i = Impl::new();
+        let orig: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+        let ecc = i.write(orig.clone()).unwrap();
+
+        let (back, report) = i.read_with_report(ecc.clone()).unwrap();
+        assert_eq!(orig, back);
+        assert_eq!(0, report.corrected);
+        assert_eq!(0, report.uncorrectable);
+
+        let mut damaged = ecc.clone();
+        damaged[100] ^= 0x01;
+        damaged[500] ^= 0x10;
+        let (back, report) = i.read_with_report(damaged.clone()).unwrap();
+        assert_eq!(orig, back);
+        assert_eq!(2, report.corrected);
+        assert_eq!(0, report.uncorrectable);
+        assert_eq!(orig, i.read(damaged).unwrap());
+    }
+
+    #[test]
+    fn corrects_a_flip_in_every_bit_position() {
+        let i = Impl::new();
+        let orig: Vec<u8> = (0..100).map(|i| (i * 7 % 256) as u8).collect();
+        let ecc = i.write(orig.clone()).unwrap();
+
+        // Blocks are 64 bits, so this flips every position of every block, including the first one with the length
+        for bit in 0..ecc.len() * 8 {
+            let mut damaged = ecc.clone();
+            damaged[bit / 8] ^= 1 << (bit % 8);
+
+            let (back, report) = i.read_with_report(damaged).unwrap();
+            assert_eq!(orig, back, "bit {}", bit);
+            assert_eq!((1, 0), (report.corrected, report.uncorrectable), "bit {}", bit);
+        }
+    }
+
     fn round_trip_test(orig: Vec<u8>) {
         let i = Impl::new();
 
@@ -137,3 +137,20 @@ impl EncryptorImpl for NoneEncryptor {
         Ok(data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::encrypt::Encryptor;
+
+    #[test]
+    fn round_trips() {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        for name in ["ChaCha20Poly1305 (sw)", "ChaCha20Poly1305"] {
+            let encryptor = Encryptor::build_by_name(name, "pass").unwrap();
+            let (_, encrypted) = encryptor.encrypt(data.clone()).unwrap();
+            assert_eq!(data.len() + encryptor.get_extra_space_needed() as usize, encrypted.len());
+            assert_eq!(data, encryptor.decrypt(encrypted).unwrap(), "{}", name);
+        }
+    }
+}
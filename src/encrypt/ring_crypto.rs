@@ -28,13 +28,13 @@ impl RingEncryptor {
         RingEncryptor { algo, key }
     }
 
-    fn create_nonce() -> aead::Nonce {
+    fn create_nonce() -> [u8; aead::NONCE_LEN] {
         let mut rand_generator = rand::rngs::OsRng::default();
 
-        let mut nonce = [0u8; 96 / 8];
+        let mut nonce = [0u8; aead::NONCE_LEN];
         rand_generator.fill_bytes(&mut nonce);
 
-        aead::Nonce::assume_unique_for_key(nonce)
+        nonce
     }
 
     fn create_ad() -> aead::Aad<[u8; 0]> {
@@ -46,23 +46,32 @@ impl RingEncryptor {
 
 impl EncryptorImpl for RingEncryptor {
     fn get_extra_space_needed(&self) -> u32 {
-        self.algo.tag_len() as u32
+        (self.algo.tag_len() + aead::NONCE_LEN) as u32
     }
 
+    /// The nonce is stored after the tag.
     fn encrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
         let nonce = Self::create_nonce();
         let ad = Self::create_ad();
 
-        self.key.seal_in_place_append_tag(nonce, ad, &mut data)?;
+        self.key
+            .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), ad, &mut data)?;
+        data.extend_from_slice(&nonce);
 
         Ok(data)
     }
 
     fn decrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
-        let nonce = Self::create_nonce();
+        let nonce_start = data
+            .len()
+            .checked_sub(aead::NONCE_LEN)
+            .context("encrypted data too small")?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&data[nonce_start..])?;
+        data.truncate(nonce_start);
         let ad = Self::create_ad();
 
-        self.key.open_in_place(nonce, ad, &mut data)?;
+        let size = self.key.open_in_place(nonce, ad, &mut data)?.len();
+        data.truncate(size);
 
         Ok(data)
     }
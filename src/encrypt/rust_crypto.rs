@@ -22,20 +22,28 @@ impl ChaCha20Poly1305Encryptor {
 
 impl EncryptorImpl for ChaCha20Poly1305Encryptor {
     fn get_extra_space_needed(&self) -> u32 {
-        <ChaCha20Poly1305 as AeadCore>::TagSize::to_u32()
+        <ChaCha20Poly1305 as AeadCore>::TagSize::to_u32() + <ChaCha20Poly1305 as AeadCore>::NonceSize::to_u32()
     }
 
+    /// The nonce is stored after the tag.
     fn encrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
         let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
         let ad = [0u8; 0];
 
         self.cipher.encrypt_in_place(&nonce, &ad, &mut data)?;
+        data.extend_from_slice(&nonce);
 
         Ok(data)
     }
 
     fn decrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
-        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let nonce_size = <ChaCha20Poly1305 as AeadCore>::NonceSize::to_usize();
+        let nonce_start = data
+            .len()
+            .checked_sub(nonce_size)
+            .context("encrypted data too small")?;
+        let nonce = GenericArray::clone_from_slice(&data[nonce_start..]);
+        data.truncate(nonce_start);
         let ad = [0u8; 0];
 
         self.cipher.decrypt_in_place(&nonce, &ad, &mut data)?;
@@ -6,12 +6,17 @@ pub use anyhow::{Error, Result};
 pub mod chunk;
 pub mod compress;
 mod db;
+pub mod delta;
 pub mod ecc;
 pub mod encrypt;
 pub mod hash;
 mod metrics;
 pub mod pack;
+pub mod par2;
 pub mod path_walk;
 pub mod pipeline;
+pub mod repository;
+pub mod restore;
 pub mod snapshot;
+pub mod storage;
 pub mod workspace;
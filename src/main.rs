@@ -1,27 +1,119 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
 use flume::{Receiver, Sender};
+use relative_path::RelativePathBuf;
 
+use mfsb::par2::Par2Config;
+use mfsb::path_walk::{WalkFilters, WalkOptions};
+use mfsb::pipeline::{ChangedFilePolicy, PipelineOptions};
+use mfsb::repository::parity::{ParityConfig, ParityGroupBuilder};
+use mfsb::repository::{Par2Status, Repository};
 use mfsb::snapshot::builder::SnapshotBuilder;
+use mfsb::snapshot::source::StreamSource;
 use mfsb::*;
 
 fn main() -> Result<()> {
     let mut ws = workspace::Workspace::build()?;
+    let repository = ws.get_repository()?;
 
-    let root = PathBuf::from("C:\\Users\\rdomenecci\\Books");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("repair") => repair(&repository),
+        Some("scrub") => scrub(&repository),
+        Some("par2") => {
+            let redundancy = args
+                .get(1)
+                .context("usage: par2 <redundancy percent> [<slice size>] | par2 off")?;
 
-    let folder = ws.get_shared_item(&root)?;
+            set_par2(&repository, redundancy, args.get(2))
+        }
+        Some("parity") => {
+            let data_packs = args
+                .get(1)
+                .context("usage: parity <data packs> <parity packs> | parity off")?;
 
-    let snapshot = SnapshotBuilder::new(folder);
-    create_snapshot(snapshot);
+            set_parity(&repository, data_packs, args.get(2))
+        }
+        Some("backup-stdin") => {
+            let name = args.get(1).context("usage: backup-stdin <name>")?;
 
-    Ok(())
+            backup_stream(name, StreamSource::Stdin, repository)
+        }
+        Some("filters") => {
+            let path = args
+                .get(1)
+                .context("usage: filters <path> [--max-size <bytes>] [--min-age <secs>] [--max-age <secs>] [--owner <uid>]... [--keep-cache-dirs]")?;
+
+            set_filters(&mut ws, &PathBuf::from(path), &args[2..])
+        }
+        Some("backup-command") => {
+            let name = args
+                .get(1)
+                .filter(|_| args.len() > 2)
+                .context("usage: backup-command <name> <program> [args...]")?;
+
+            backup_stream(name, StreamSource::Command(args[2..].to_vec()), repository)
+        }
+        _ => {
+            let root = PathBuf::from("C:\\Users\\rdomenecci\\Books");
+
+            let folder = ws.get_shared_item(&root)?;
+
+            let mut options = WalkOptions {
+                rules: ws.get_path_rules()?,
+                filters: folder.filters.clone(),
+                ..Default::default()
+            };
+            let mut pipeline_options = PipelineOptions {
+                prepare_threads: 1,
+                ..Default::default()
+            };
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                let mut value = || {
+                    args.next()
+                        .with_context(|| format!("missing value for {}", arg))
+                };
+
+                match arg.as_str() {
+                    "--changed-files" => {
+                        pipeline_options.changed_files = match value()?.as_str() {
+                            "keep" => ChangedFilePolicy::Keep,
+                            "fail" => ChangedFilePolicy::Fail,
+                            retries => ChangedFilePolicy::Retry(retries.parse()?),
+                        }
+                    }
+                    "--compressor" => {
+                        let compressor = compress::Compressor::build_by_name(value()?)?;
+                        pipeline_options.compressor = Some(compressor.get_name().to_string());
+                    }
+                    "--memory-budget" => pipeline_options.memory_budget = value()?.parse()?,
+                    "--exclude" => options.rules.add_exclude(value()?)?,
+                    "--include" => options.rules.add_include(value()?)?,
+                    "--follow-symlinks" => options.follow_symlinks = true,
+                    "--skip-special-files" => options.skip_special_files = true,
+                    "--one-file-system" => options.one_file_system = true,
+                    "--skip-fs-type" => options.skip_fs_types.push(value()?.clone()),
+                    "--walk-threads" => options.threads = value()?.parse()?,
+                    _ => return Err(Error::msg(format!("unknown argument: {}", arg))),
+                }
+            }
+
+            let snapshot = SnapshotBuilder::new(folder, options);
+            create_snapshot(snapshot.clone(), repository, pipeline_options);
+
+            println!("{}", snapshot.get_summary());
+
+            Ok(())
+        }
+    }
 }
 
-fn create_snapshot(snapshot: Arc<SnapshotBuilder>) {
-    let (pipeline, tx, rx) = pipeline::Pipeline::new(1);
+fn create_snapshot(snapshot: Arc<SnapshotBuilder>, repository: Arc<Repository>, options: PipelineOptions) {
+    let (pipeline, tx, rx) = pipeline::Pipeline::new(options, repository);
 
     tx.send(snapshot.clone()).unwrap();
     drop(tx);
@@ -32,3 +124,149 @@ fn create_snapshot(snapshot: Arc<SnapshotBuilder>) {
 
     assert!(snapshot.is_complete());
 }
+
+/// Replaces the filters used when backing up a shared item.
+fn set_filters(ws: &mut workspace::Workspace, path: &Path, args: &[String]) -> Result<()> {
+    let mut item = ws.get_shared_item(path)?;
+
+    let mut filters = WalkFilters::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("missing value for {}", arg))
+        };
+        let mut secs = || anyhow::Ok(Duration::from_secs(value()?.parse()?));
+
+        match arg.as_str() {
+            "--max-size" => filters.max_size = Some(value()?.parse()?),
+            "--min-age" => filters.min_age = Some(secs()?),
+            "--max-age" => filters.max_age = Some(secs()?),
+            "--owner" => filters.owners.push(value()?.parse()?),
+            "--keep-cache-dirs" => filters.skip_cache_dirs = false,
+            _ => return Err(Error::msg(format!("unknown argument: {}", arg))),
+        }
+    }
+
+    item.filters = filters;
+    ws.update_shared_item(&item)?;
+
+    println!("{}", item.filters);
+
+    Ok(())
+}
+
+/// Backs up the data read from `source` as a file named `name`.
+fn backup_stream(name: &str, source: StreamSource, repository: Arc<Repository>) -> Result<()> {
+    let snapshot = SnapshotBuilder::new_stream(RelativePathBuf::from(name).normalize(), source);
+    let options = PipelineOptions {
+        prepare_threads: 1,
+        ..Default::default()
+    };
+    create_snapshot(snapshot.clone(), repository, options);
+
+    match snapshot.take_error() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn repair(repository: &Repository) -> Result<()> {
+    for pack in repository.list_packs()? {
+        match repository.repair_pack_with_par2(&pack) {
+            Ok(Par2Status::Repaired) => println!("Repaired {} using PAR2", pack),
+            Err(err) => println!("Could not repair {} using PAR2: {}", pack, err),
+            _ => {}
+        }
+    }
+
+    let report = repository.repair()?;
+
+    for name in &report.repaired {
+        println!("Repaired {}", name);
+    }
+    for (group, err) in &report.failed {
+        println!("Could not repair {}: {}", group, err);
+    }
+    println!(
+        "Checked {} files in {} parity groups: {} repaired, {} failed",
+        report.checked,
+        report.groups,
+        report.repaired.len(),
+        report.failed.len()
+    );
+
+    if report.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::msg("some packs could not be repaired"))
+    }
+}
+
+/// Changes if new packs get PAR2 recovery files.
+fn set_par2(repository: &Repository, redundancy: &str, slice_size: Option<&String>) -> Result<()> {
+    let mut config = repository.init()?;
+
+    config.par2 = match redundancy {
+        "off" => None,
+        _ => Some(Par2Config {
+            redundancy_percent: redundancy.parse()?,
+            slice_size: match slice_size {
+                Some(size) => size.parse()?,
+                None => Par2Config::default().slice_size,
+            },
+        }),
+    };
+    repository.write_config(&config)?;
+
+    match config.par2 {
+        Some(par2) => println!(
+            "New packs will get PAR2 recovery files with {}% redundancy and {} byte slices",
+            par2.redundancy_percent, par2.slice_size
+        ),
+        None => println!("New packs will not get PAR2 recovery files"),
+    }
+
+    Ok(())
+}
+
+/// Changes if new packs are grouped and get parity packs.
+fn set_parity(repository: &Repository, data_packs: &str, parity_packs: Option<&String>) -> Result<()> {
+    let mut config = repository.init()?;
+
+    config.parity = match data_packs {
+        "off" => None,
+        _ => Some(ParityConfig {
+            data_packs: data_packs.parse()?,
+            parity_packs: parity_packs
+                .context("usage: parity <data packs> <parity packs> | parity off")?
+                .parse()?,
+        }),
+    };
+    if let Some(parity) = config.parity {
+        ParityGroupBuilder::new(parity)?;
+    }
+    repository.write_config(&config)?;
+
+    match config.parity {
+        Some(parity) => println!(
+            "New packs will be grouped {} at a time with {} parity packs",
+            parity.data_packs, parity.parity_packs
+        ),
+        None => println!("New packs will not get parity packs"),
+    }
+
+    Ok(())
+}
+
+fn scrub(repository: &Repository) -> Result<()> {
+    let report = repository.scrub()?;
+
+    println!("{}", report);
+
+    if report.get_damaged() == 0 {
+        Ok(())
+    } else {
+        Err(Error::msg("some packs are damaged, run repair to rebuild them"))
+    }
+}
@@ -1,9 +1,11 @@
 use std::cmp::max;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 pub struct ResponseTime {
     histogram: Arc<Mutex<hdrhistogram::Histogram<u64>>>,
+    sum: Arc<AtomicU64>,
     clock: quanta::Clock,
     start: Option<u64>,
     total: Duration,
@@ -13,6 +15,7 @@ impl ResponseTime {
     pub fn new(clock: quanta::Clock) -> ResponseTime {
         Self {
             histogram: Arc::new(Mutex::new(hdrhistogram::Histogram::new(2).unwrap())),
+            sum: Arc::new(AtomicU64::new(0)),
             clock,
             start: None,
             total: Duration::default(),
@@ -37,6 +40,7 @@ impl ResponseTime {
 
         let val = max(self.total.as_micros(), 1);
         self.histogram.lock().unwrap().record(val as u64).unwrap();
+        self.sum.fetch_add(val as u64, Ordering::SeqCst);
 
         self.total = Duration::default();
     }
@@ -49,6 +53,10 @@ impl ResponseTime {
         self.histogram.lock().unwrap().len()
     }
 
+    pub fn get_sum(&self) -> Duration {
+        Duration::from_micros(self.sum.load(Ordering::SeqCst))
+    }
+
     pub fn get_average(&self) -> Duration {
         Duration::from_micros(self.histogram.lock().unwrap().mean().round() as u64)
     }
@@ -70,6 +78,7 @@ impl Clone for ResponseTime {
     fn clone(&self) -> Self {
         Self {
             histogram: self.histogram.clone(),
+            sum: self.sum.clone(),
             clock: self.clock.clone(),
             start: None,
             total: Default::default(),
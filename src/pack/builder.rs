@@ -3,9 +3,11 @@ use std::time::Instant;
 
 use anyhow::Error;
 
+use crate::chunk::ChunkData;
 use crate::compress::CompressionType;
 use crate::ecc::ECCType;
 use crate::encrypt::EncryptorType;
+use crate::pack::header::PackHeader;
 use crate::snapshot::builder::{ChunkBuilder, PathBuilder, SnapshotBuilder};
 
 pub struct PackBuilder {
@@ -14,6 +16,7 @@ pub struct PackBuilder {
     hash: Vec<u8>,
     chunks_size: u32,
     compress_type: Option<CompressionType>,
+    compress_level: Option<i32>,
     compress_size: u32,
     encrypt_type: Option<EncryptorType>,
     encrypt_size: u32,
@@ -31,6 +34,7 @@ impl PackBuilder {
             hash: Vec::new(),
             chunks_size: 0,
             compress_type: None,
+            compress_level: None,
             compress_size: 0,
             encrypt_type: None,
             encrypt_size: 0,
@@ -46,14 +50,14 @@ impl PackBuilder {
         snapshot: Arc<SnapshotBuilder>,
         file: Arc<PathBuilder>,
         chunk: Arc<ChunkBuilder>,
-        mut data: Vec<u8>,
+        data: ChunkData,
     ) {
         let self_data = self.data.as_mut().unwrap();
         let start = self_data.len() as u32;
         let size = data.len() as u32;
 
         self.chunks.push((snapshot, file, chunk, start, size));
-        self_data.append(&mut data);
+        self_data.extend_from_slice(&data);
         self.chunks_size = self_data.len() as u32;
     }
 
@@ -65,6 +69,10 @@ impl PackBuilder {
         self.compress_size
     }
 
+    pub fn get_compress_level(&self) -> Option<i32> {
+        self.compress_level
+    }
+
     pub fn get_size_encrypt(&self) -> u32 {
         self.encrypt_size
     }
@@ -77,12 +85,17 @@ impl PackBuilder {
         self.data.take().unwrap()
     }
 
+    pub fn get_hash(&self) -> &[u8] {
+        &self.hash
+    }
+
     pub fn set_hash(&mut self, hash: Vec<u8>) {
         self.hash = hash;
     }
 
-    pub fn set_compressed_data(&mut self, ct: CompressionType, data: Vec<u8>) {
+    pub fn set_compressed_data(&mut self, ct: CompressionType, level: Option<i32>, data: Vec<u8>) {
         self.compress_type = Some(ct);
+        self.compress_level = level;
         self.compress_size = data.len() as u32;
         self.data = Some(data);
     }
@@ -99,6 +112,12 @@ impl PackBuilder {
         self.data = Some(data);
     }
 
+    /// Adds the header copies around the data, creating the final contents of the pack.
+    pub fn set_header(&mut self, header: &PackHeader) {
+        let data = header.write_pack(self.get_data());
+        self.data = Some(data);
+    }
+
     pub fn set_error(&mut self, error: Error) {
         *self.error.lock().unwrap() = Some(error);
     }
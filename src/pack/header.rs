@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+
+const MAGIC: &[u8; 4] = b"mfPK";
+const VERSION: u8 = 1;
+
+/// What is needed to read a pack back: the algorithms that were applied to its data and its size.
+///
+/// A copy of the header is stored at both ends of the pack, each protected by a CRC32, so the pack can still be read if
+/// one of them is damaged. Layout: header, data, header, size of a header copy (u32).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackHeader {
+    /// Hash of the chunks data, before compression.
+    pub hash: Vec<u8>,
+    pub hasher: String,
+    pub chunks_size: u64,
+    pub compressor: String,
+    /// The level the compressor used, if it has levels. Adaptive compressors use a different one for each pack.
+    pub compression_level: Option<i32>,
+    pub encryptor: String,
+    pub ecc: String,
+    pub data_size: u64,
+}
+
+impl PackHeader {
+    /// Creates the final contents of the pack from its data (after ECC).
+    pub fn write_pack(&self, data: &[u8]) -> Vec<u8> {
+        let header = self.encode();
+
+        let mut result = Vec::with_capacity(2 * header.len() + data.len() + 4);
+        result.extend_from_slice(&header);
+        result.extend_from_slice(data);
+        result.extend_from_slice(&header);
+        result.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        result
+    }
+
+    /// Reads the header of a pack, falling back to the copy at the end of it. Returns the header and the data (that
+    /// may be truncated, if the pack was).
+    pub fn read_pack(pack: &[u8]) -> Result<(PackHeader, &[u8])> {
+        let (header, header_size) = match Self::decode(pack) {
+            Some(r) => r,
+            None => Self::read_trailer(pack).context("Can't read pack: both headers are damaged")?,
+        };
+
+        let start = header_size.min(pack.len());
+        let end = (header_size as u64 + header.data_size).min(pack.len() as u64) as usize;
+
+        Ok((header, &pack[start..end]))
+    }
+
+    fn read_trailer(pack: &[u8]) -> Option<(PackHeader, usize)> {
+        let size_start = pack.len().checked_sub(4)?;
+        let header_size = u32::from_le_bytes(pack[size_start..].try_into().unwrap()) as usize;
+
+        let (header, size) = Self::decode(&pack[size_start.checked_sub(header_size)?..size_start])?;
+
+        (size == header_size).then_some((header, header_size))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![VERSION];
+        write_bytes(&mut payload, &self.hash);
+        write_bytes(&mut payload, self.hasher.as_bytes());
+        payload.extend_from_slice(&self.chunks_size.to_le_bytes());
+        write_bytes(&mut payload, self.compressor.as_bytes());
+        write_bytes(
+            &mut payload,
+            &self
+                .compression_level
+                .map(i32::to_le_bytes)
+                .unwrap_or_default(),
+        );
+        write_bytes(&mut payload, self.encryptor.as_bytes());
+        write_bytes(&mut payload, self.ecc.as_bytes());
+        payload.extend_from_slice(&self.data_size.to_le_bytes());
+
+        let mut result = Vec::with_capacity(payload.len() + 12);
+        result.extend_from_slice(MAGIC);
+        result.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        result.extend_from_slice(&payload);
+        let crc = crc32fast::hash(&result);
+        result.extend_from_slice(&crc.to_le_bytes());
+        result
+    }
+
+    /// Returns the header and the size of its encoded form, or `None` if it is damaged.
+    fn decode(data: &[u8]) -> Option<(PackHeader, usize)> {
+        if data.get(..4)? != MAGIC {
+            return None;
+        }
+
+        let payload_size = u32::from_le_bytes(data.get(4..8)?.try_into().unwrap()) as usize;
+        let crc_start = 8usize.checked_add(payload_size)?;
+        let crc = u32::from_le_bytes(data.get(crc_start..crc_start + 4)?.try_into().unwrap());
+        if crc32fast::hash(&data[..crc_start]) != crc {
+            return None;
+        }
+
+        let header = Self::parse(&data[8..crc_start]).ok()?;
+
+        Some((header, crc_start + 4))
+    }
+
+    fn parse(mut payload: &[u8]) -> Result<PackHeader> {
+        let version = read(&mut payload, 1)?[0];
+        anyhow::ensure!(version == VERSION, "unknown pack version: {}", version);
+
+        let hash = read_bytes(&mut payload)?.to_vec();
+        let hasher = String::from_utf8(read_bytes(&mut payload)?.to_vec())?;
+        let chunks_size = u64::from_le_bytes(read(&mut payload, 8)?.try_into().unwrap());
+        let compressor = String::from_utf8(read_bytes(&mut payload)?.to_vec())?;
+        let compression_level = match read_bytes(&mut payload)? {
+            [] => None,
+            level => Some(i32::from_le_bytes(level.try_into()?)),
+        };
+        let encryptor = String::from_utf8(read_bytes(&mut payload)?.to_vec())?;
+        let ecc = String::from_utf8(read_bytes(&mut payload)?.to_vec())?;
+        let data_size = u64::from_le_bytes(read(&mut payload, 8)?.try_into().unwrap());
+
+        Ok(PackHeader {
+            hash,
+            hasher,
+            chunks_size,
+            compressor,
+            compression_level,
+            encryptor,
+            ecc,
+            data_size,
+        })
+    }
+}
+
+fn write_bytes(result: &mut Vec<u8>, data: &[u8]) {
+    result.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    result.extend_from_slice(data);
+}
+
+fn read<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8]> {
+    anyhow::ensure!(data.len() >= size, "pack header too small");
+
+    let (result, rest) = data.split_at(size);
+    *data = rest;
+    Ok(result)
+}
+
+fn read_bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let size = u16::from_le_bytes(read(data, 2)?.try_into().unwrap()) as usize;
+    read(data, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pack::header::PackHeader;
+
+    #[test]
+    fn falls_back_to_the_trailing_header() {
+        let header = PackHeader {
+            hash: vec![1, 2, 3],
+            hasher: "Blake3".to_string(),
+            chunks_size: 2000,
+            compressor: "zstd-auto".to_string(),
+            compression_level: Some(7),
+            encryptor: "ChaCha20Poly1305".to_string(),
+            ecc: "SECDED".to_string(),
+            data_size: 1000,
+        };
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let pack = header.write_pack(&data);
+
+        assert_eq!((header.clone(), &data[..]), PackHeader::read_pack(&pack).unwrap());
+
+        let mut damaged = pack.clone();
+        damaged[10] ^= 0x01;
+        assert_eq!((header.clone(), &data[..]), PackHeader::read_pack(&damaged).unwrap());
+
+        let mut damaged = pack.clone();
+        damaged[pack.len() - 10] ^= 0x01;
+        assert_eq!((header.clone(), &data[..]), PackHeader::read_pack(&damaged).unwrap());
+
+        let (_, truncated) = PackHeader::read_pack(&pack[..500]).unwrap();
+        assert_eq!(&data[..500 - (pack.len() - data.len() - 4) / 2], truncated);
+
+        let mut damaged = pack.clone();
+        damaged[10] ^= 0x01;
+        damaged[pack.len() - 10] ^= 0x01;
+        assert!(PackHeader::read_pack(&damaged).is_err());
+    }
+}
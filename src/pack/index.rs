@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+
+use crate::delta;
+use crate::delta::Sketch;
+use crate::pack::location::PackLocation;
+
+/// Where each chunk is stored and, for chunks stored as deltas, which chunk is their base.
+pub struct ChunkIndex {
+    data: Mutex<ChunkIndexData>,
+}
+
+#[derive(Clone)]
+pub struct ChunkIndexEntry {
+    pub size: u32,
+    pub base: Option<Vec<u8>>,
+    pub location: Option<PackLocation>,
+}
+
+struct ChunkIndexData {
+    chunks: HashMap<Vec<u8>, ChunkIndexEntry>,
+    super_features: HashMap<(usize, u64), Vec<u8>>,
+}
+
+impl ChunkIndex {
+    pub fn new() -> Arc<ChunkIndex> {
+        Arc::new(ChunkIndex {
+            data: Mutex::new(ChunkIndexData {
+                chunks: HashMap::new(),
+                super_features: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Adds a chunk to the index. Returns false if it was already there, in which case the existing entry is kept.
+    pub fn add(&self, hash: &[u8], size: u32, base: Option<Vec<u8>>) -> bool {
+        let mut data = self.data.lock().unwrap();
+
+        if data.chunks.contains_key(hash) {
+            return false;
+        }
+
+        data.chunks.insert(
+            hash.to_vec(),
+            ChunkIndexEntry {
+                size,
+                base,
+                location: None,
+            },
+        );
+        true
+    }
+
+    pub fn add_sketch(&self, hash: &[u8], sketch: &Sketch) {
+        let mut data = self.data.lock().unwrap();
+
+        for (i, sf) in sketch.super_features.iter().enumerate() {
+            data.super_features.insert((i, *sf), hash.to_vec());
+        }
+    }
+
+    /// Finds the chunk that shares the most super-features with the sketch.
+    pub fn find_similar(&self, sketch: &Sketch) -> Option<Vec<u8>> {
+        let data = self.data.lock().unwrap();
+
+        let mut candidates: Vec<(&Vec<u8>, usize)> = Vec::new();
+        for (i, sf) in sketch.super_features.iter().enumerate() {
+            if let Some(hash) = data.super_features.get(&(i, *sf)) {
+                match candidates.iter_mut().find(|(h, _)| *h == hash) {
+                    Some((_, count)) => *count += 1,
+                    None => candidates.push((hash, 1)),
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(hash, _)| hash.clone())
+    }
+
+    pub fn set_stored(&self, hash: &[u8], location: PackLocation) {
+        let mut data = self.data.lock().unwrap();
+
+        if let Some(entry) = data.chunks.get_mut(hash) {
+            if entry.location.is_none() {
+                entry.location = Some(location);
+            }
+        }
+    }
+
+    pub fn get(&self, hash: &[u8]) -> Option<ChunkIndexEntry> {
+        self.data.lock().unwrap().chunks.get(hash).cloned()
+    }
+
+    /// Reads the contents of a chunk, applying its delta to its base (read recursively) when needed.
+    pub fn read_chunk(
+        &self,
+        hash: &[u8],
+        read_stored: &mut dyn FnMut(&PackLocation) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let entry = self.get(hash).context("chunk not found in index")?;
+        let location = entry
+            .location
+            .as_ref()
+            .context("chunk was not stored yet")?;
+
+        let stored = read_stored(location)?;
+
+        let result = match &entry.base {
+            None => stored,
+            Some(base_hash) => {
+                let base = self
+                    .read_chunk(base_hash, read_stored)
+                    .context("error reading delta base")?;
+                delta::decode(&base, &stored)?
+            }
+        };
+
+        anyhow::ensure!(
+            result.len() == entry.size as usize,
+            "chunk should have {} bytes but has {}",
+            entry.size,
+            result.len()
+        );
+
+        Ok(result)
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PackLocation {
     pub hash: Vec<u8>,
     pub start: u64,
@@ -1,4 +1,7 @@
 pub use location::PackLocation;
 
 pub mod builder;
+pub mod header;
+pub mod index;
 pub mod location;
+pub mod reader;
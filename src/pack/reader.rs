@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::compress::Compressor;
+use crate::ecc::ECC;
+use crate::encrypt::Encryptor;
+use crate::hash::Hasher;
+use crate::pack::header::PackHeader;
+use crate::pack::PackLocation;
+use crate::repository::Repository;
+
+/// A pack read back from the repository, with all the steps of the pipeline undone and its hash verified.
+pub struct Pack {
+    pub header: PackHeader,
+    pub data: Vec<u8>,
+}
+
+/// Reads packs from the repository. Keeps the last pack read, as chunks are usually read in the order they were
+/// stored.
+pub struct PackReader {
+    repository: Arc<Repository>,
+    password: String,
+    encryptors: HashMap<String, Arc<Encryptor>>,
+    last: Option<Arc<Pack>>,
+}
+
+impl PackReader {
+    pub fn new(repository: Arc<Repository>, password: &str) -> PackReader {
+        PackReader {
+            repository,
+            password: password.to_string(),
+            encryptors: HashMap::new(),
+            last: None,
+        }
+    }
+
+    pub fn read_pack(&mut self, hash: &[u8]) -> Result<Arc<Pack>> {
+        if let Some(last) = self.last.as_ref().filter(|p| p.header.hash == hash) {
+            return Ok(last.clone());
+        }
+
+        let name = Repository::get_pack_name(hash);
+        let pack = self
+            .read_pack_uncached(hash)
+            .with_context(|| format!("error reading pack {}", name))?;
+
+        let pack = Arc::new(pack);
+        self.last = Some(pack.clone());
+        Ok(pack)
+    }
+
+    pub fn read_chunk(&mut self, location: &PackLocation) -> Result<Vec<u8>> {
+        let pack = self.read_pack(&location.hash)?;
+
+        let data = pack
+            .data
+            .get(location.start as usize..(location.start + location.size) as usize)
+            .context("chunk is outside of the pack")?;
+
+        Ok(data.to_vec())
+    }
+
+    fn read_pack_uncached(&mut self, hash: &[u8]) -> Result<Pack> {
+        let stored = self.repository.read_pack(hash)?;
+
+        let (header, data) = PackHeader::read_pack(&stored)?;
+        anyhow::ensure!(header.hash == hash, "pack header belongs to another pack");
+
+        let data = ECC::build_by_name(&header.ecc)?.read(data.to_vec())?;
+
+        let encryptor = match self.encryptors.get(&header.encryptor) {
+            Some(e) => e.clone(),
+            None => {
+                let e = Encryptor::build_by_name(&header.encryptor, &self.password)?;
+                self.encryptors.insert(header.encryptor.clone(), e.clone());
+                e
+            }
+        };
+        let data = encryptor.decrypt(data)?;
+
+        let data = Compressor::build_by_name(&header.compressor)?.decompress(data, header.chunks_size as u32)?;
+
+        let hasher = Hasher::build_by_name(&header.hasher)?;
+        anyhow::ensure!(
+            data.len() as u64 == header.chunks_size && hasher.hash(&data) == header.hash,
+            "pack data does not match its hash"
+        );
+
+        Ok(Pack { header, data })
+    }
+}
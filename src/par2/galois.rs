@@ -0,0 +1,93 @@
+//! Arithmetic in GF(2^16) with the generator polynomial used by PAR2 (x^16 + x^12 + x^3 + x + 1).
+
+const POLYNOMIAL: u32 = 0x1100B;
+const LIMIT: usize = 65535;
+
+lazy_static! {
+    static ref TABLES: (Vec<u16>, Vec<u16>) = create_tables();
+}
+
+fn create_tables() -> (Vec<u16>, Vec<u16>) {
+    let mut log = vec![0u16; LIMIT + 1];
+    let mut alog = vec![0u16; LIMIT + 1];
+
+    let mut b: u32 = 1;
+    for (l, a) in alog.iter_mut().take(LIMIT).enumerate() {
+        log[b as usize] = l as u16;
+        *a = b as u16;
+
+        b <<= 1;
+        if b & 0x10000 != 0 {
+            b ^= POLYNOMIAL;
+        }
+    }
+    alog[LIMIT] = alog[0];
+
+    (log, alog)
+}
+
+pub fn log(a: u16) -> usize {
+    TABLES.0[a as usize] as usize
+}
+
+pub fn alog(l: usize) -> u16 {
+    TABLES.1[l % LIMIT]
+}
+
+pub fn mul(a: u16, b: u16) -> u16 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    alog(log(a) + log(b))
+}
+
+pub fn div(a: u16, b: u16) -> u16 {
+    assert_ne!(b, 0, "division by zero");
+    if a == 0 {
+        return 0;
+    }
+    alog(log(a) + LIMIT - log(b))
+}
+
+pub fn pow(a: u16, e: u32) -> u16 {
+    if e == 0 {
+        return 1;
+    }
+    if a == 0 {
+        return 0;
+    }
+    alog(log(a) * (e as usize % LIMIT))
+}
+
+/// The constants PAR2 assigns to the input slices: powers of 2 whose exponent is coprime with 65535.
+pub fn input_slice_bases(count: usize) -> Vec<u16> {
+    let mut result = Vec::with_capacity(count);
+
+    let mut l = 0;
+    while result.len() < count {
+        l += 1;
+        if l % 3 != 0 && l % 5 != 0 && l % 17 != 0 && l % 257 != 0 {
+            result.push(alog(l));
+        }
+    }
+
+    result
+}
+
+/// dst += c * src, with both slices interpreted as little endian 16 bit words.
+pub fn mul_add_slice(dst: &mut [u8], src: &[u8], c: u16) {
+    if c == 0 {
+        return;
+    }
+
+    let log_c = log(c);
+    for (d, s) in dst.chunks_exact_mut(2).zip(src.chunks_exact(2)) {
+        let s = u16::from_le_bytes([s[0], s[1]]);
+        if s == 0 {
+            continue;
+        }
+
+        let v = u16::from_le_bytes([d[0], d[1]]) ^ alog(log(s) + log_c);
+        d.copy_from_slice(&v.to_le_bytes());
+    }
+}
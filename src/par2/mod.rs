@@ -0,0 +1,438 @@
+//! Standard PAR2 recovery files, so packs can be verified and repaired with common tools (like par2cmdline) even
+//! without mfsb, plus a reader able to use them.
+
+use std::cmp::min;
+
+use anyhow::{Context, Error, Result};
+use md5::{Digest, Md5};
+
+mod galois;
+
+const MAGIC: &[u8; 8] = b"PAR2\0PKT";
+const HEADER_SIZE: usize = 64;
+const TYPE_MAIN: &[u8; 16] = b"PAR 2.0\0Main\0\0\0\0";
+const TYPE_FILE_DESC: &[u8; 16] = b"PAR 2.0\0FileDesc";
+const TYPE_IFSC: &[u8; 16] = b"PAR 2.0\0IFSC\0\0\0\0";
+const TYPE_RECOVERY: &[u8; 16] = b"PAR 2.0\0RecvSlic";
+const TYPE_CREATOR: &[u8; 16] = b"PAR 2.0\0Creator\0";
+const CREATOR: &str = "mfsb";
+const MAX_SLICES: usize = 32768;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Par2Config {
+    /// Size of each slice. Rounded up to a multiple of 4 and grown if the file would have too many slices.
+    pub slice_size: u64,
+    /// Recovery data to create, as a percentage of the file size. At least one recovery slice is always created.
+    pub redundancy_percent: u32,
+}
+
+impl Default for Par2Config {
+    fn default() -> Self {
+        Self {
+            slice_size: 64 * 1024,
+            redundancy_percent: 10,
+        }
+    }
+}
+
+/// Creates the recovery files for a file with the given name (relative to the recovery files) and contents. Returns
+/// the suffixes to add to the file name and the contents of each recovery file: an index file with only the
+/// description of the file and a volume file with the recovery slices.
+pub fn create(file_name: &str, data: &[u8], config: &Par2Config) -> Result<Vec<(String, Vec<u8>)>> {
+    anyhow::ensure!(file_name.is_ascii(), "PAR2 file names must be ASCII");
+
+    let mut slice_size = config.slice_size.max(4).next_multiple_of(4);
+    while (data.len() as u64).div_ceil(slice_size) > MAX_SLICES as u64 {
+        slice_size *= 2;
+    }
+    let slice_size = slice_size as usize;
+
+    let slices: Vec<Vec<u8>> = data
+        .chunks(slice_size)
+        .map(|s| {
+            let mut s = s.to_vec();
+            s.resize(slice_size, 0);
+            s
+        })
+        .collect();
+
+    let hash_16k = md5(&data[..min(data.len(), 16 * 1024)]);
+    let file_id = {
+        let mut h = Md5::new();
+        h.update(hash_16k);
+        h.update((data.len() as u64).to_le_bytes());
+        h.update(file_name.as_bytes());
+        to_array(&h.finalize())
+    };
+
+    let mut main = Vec::new();
+    main.extend_from_slice(&(slice_size as u64).to_le_bytes());
+    main.extend_from_slice(&1u32.to_le_bytes());
+    main.extend_from_slice(&file_id);
+    let set_id = md5(&main);
+
+    let mut file_desc = Vec::new();
+    file_desc.extend_from_slice(&file_id);
+    file_desc.extend_from_slice(&md5(data));
+    file_desc.extend_from_slice(&hash_16k);
+    file_desc.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    file_desc.extend_from_slice(file_name.as_bytes());
+    pad4(&mut file_desc);
+
+    let mut ifsc = Vec::new();
+    ifsc.extend_from_slice(&file_id);
+    for s in &slices {
+        ifsc.extend_from_slice(&md5(s));
+        ifsc.extend_from_slice(&crc32fast::hash(s).to_le_bytes());
+    }
+
+    let mut creator = CREATOR.as_bytes().to_vec();
+    pad4(&mut creator);
+
+    let mut critical = Vec::new();
+    write_packet(&mut critical, &set_id, TYPE_MAIN, &main);
+    write_packet(&mut critical, &set_id, TYPE_FILE_DESC, &file_desc);
+    write_packet(&mut critical, &set_id, TYPE_IFSC, &ifsc);
+    write_packet(&mut critical, &set_id, TYPE_CREATOR, &creator);
+
+    let recovery_count = (slices.len() * config.redundancy_percent as usize)
+        .div_ceil(100)
+        .clamp(1, MAX_SLICES);
+
+    let bases = galois::input_slice_bases(slices.len());
+
+    let mut volume = Vec::new();
+    for exponent in 0..recovery_count as u32 {
+        let mut recovery = vec![0u8; 4 + slice_size];
+        recovery[..4].copy_from_slice(&exponent.to_le_bytes());
+
+        for (slice, base) in slices.iter().zip(bases.iter()) {
+            galois::mul_add_slice(&mut recovery[4..], slice, galois::pow(*base, exponent));
+        }
+
+        write_packet(&mut volume, &set_id, TYPE_RECOVERY, &recovery);
+    }
+    volume.extend_from_slice(&critical);
+
+    Ok(vec![
+        (String::from(".par2"), critical),
+        (format!(".vol00+{:02}.par2", recovery_count), volume),
+    ])
+}
+
+/// What a set of recovery files says about a file, as read from their packets.
+pub struct RecoverySet {
+    slice_size: usize,
+    length: u64,
+    file_hash: [u8; 16],
+    slices: Vec<([u8; 16], u32)>,
+    recovery: Vec<(u32, Vec<u8>)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Intact,
+    /// Number of damaged slices and number of recovery slices available.
+    Damaged(usize, usize),
+}
+
+impl RecoverySet {
+    /// Reads the packets in the contents of one or more recovery files. Damaged packets are ignored.
+    pub fn parse(files: &[Vec<u8>]) -> Result<RecoverySet> {
+        let mut set_id = None;
+        let mut slice_size = None;
+        let mut file_desc = None;
+        let mut ifsc = None;
+        let mut recovery = Vec::new();
+
+        for data in files {
+            for (id, packet_type, body) in read_packets(data) {
+                if *set_id.get_or_insert(id) != id {
+                    continue;
+                }
+
+                match &packet_type {
+                    t if t == TYPE_MAIN && body.len() >= 12 => {
+                        slice_size = Some(u64::from_le_bytes(body[..8].try_into().unwrap()) as usize);
+                    }
+                    t if t == TYPE_FILE_DESC && body.len() >= 56 => file_desc = Some(body),
+                    t if t == TYPE_IFSC && body.len() >= 16 => ifsc = Some(body),
+                    t if t == TYPE_RECOVERY && body.len() >= 4 => {
+                        let exponent = u32::from_le_bytes(body[..4].try_into().unwrap());
+                        if !recovery.iter().any(|(e, _)| *e == exponent) {
+                            recovery.push((exponent, body[4..].to_vec()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let slice_size = slice_size.context("PAR2 main packet not found")?;
+        let file_desc = file_desc.context("PAR2 file description packet not found")?;
+        let ifsc = ifsc.context("PAR2 slice checksums packet not found")?;
+
+        anyhow::ensure!(slice_size > 0 && slice_size % 4 == 0, "invalid PAR2 slice size: {}", slice_size);
+
+        let slices = ifsc[16..]
+            .chunks_exact(20)
+            .map(|c| (to_array(&c[..16]), u32::from_le_bytes(c[16..20].try_into().unwrap())))
+            .collect();
+
+        recovery.retain(|(_, data)| data.len() == slice_size);
+
+        Ok(RecoverySet {
+            slice_size,
+            length: u64::from_le_bytes(file_desc[48..56].try_into().unwrap()),
+            file_hash: to_array(&file_desc[16..32]),
+            slices,
+            recovery,
+        })
+    }
+
+    fn get_slice(&self, data: &[u8], index: usize) -> Vec<u8> {
+        let start = min(index * self.slice_size, data.len());
+        let end = min((index + 1) * self.slice_size, min(data.len(), self.length as usize));
+
+        let mut result = data[start..end].to_vec();
+        result.resize(self.slice_size, 0);
+        result
+    }
+
+    fn find_damaged_slices(&self, data: &[u8]) -> Vec<usize> {
+        (0..self.slices.len())
+            .filter(|i| {
+                let slice = self.get_slice(data, *i);
+                let (hash, crc) = self.slices[*i];
+                crc32fast::hash(&slice) != crc || md5(&slice) != hash
+            })
+            .collect()
+    }
+
+    pub fn verify(&self, data: &[u8]) -> Verification {
+        if data.len() as u64 == self.length && md5(data) == self.file_hash {
+            return Verification::Intact;
+        }
+
+        Verification::Damaged(self.find_damaged_slices(data).len(), self.recovery.len())
+    }
+
+    /// Rebuilds the file contents using the recovery slices.
+    pub fn repair(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let damaged = self.find_damaged_slices(data);
+        anyhow::ensure!(
+            damaged.len() <= self.recovery.len(),
+            "Can't repair: {} damaged slices but only {} recovery slices (PAR2)",
+            damaged.len(),
+            self.recovery.len()
+        );
+
+        let bases = galois::input_slice_bases(self.slices.len());
+        let recovery = &self.recovery[..damaged.len()];
+
+        // Remove the contribution of the intact slices from the recovery slices
+        let mut rhs: Vec<Vec<u8>> = recovery.iter().map(|(_, r)| r.clone()).collect();
+        for i in (0..self.slices.len()).filter(|i| !damaged.contains(i)) {
+            let slice = self.get_slice(data, i);
+            for ((exponent, _), r) in recovery.iter().zip(rhs.iter_mut()) {
+                galois::mul_add_slice(r, &slice, galois::pow(bases[i], *exponent));
+            }
+        }
+
+        let matrix: Vec<Vec<u16>> = recovery
+            .iter()
+            .map(|(exponent, _)| {
+                damaged
+                    .iter()
+                    .map(|d| galois::pow(bases[*d], *exponent))
+                    .collect()
+            })
+            .collect();
+        let inverse = invert(matrix)?;
+
+        let mut result = data[..min(data.len(), self.length as usize)].to_vec();
+        result.resize(self.length as usize, 0);
+
+        for (row, d) in inverse.iter().zip(damaged.iter()) {
+            let mut slice = vec![0u8; self.slice_size];
+            for (c, r) in row.iter().zip(rhs.iter()) {
+                galois::mul_add_slice(&mut slice, r, *c);
+            }
+
+            let start = d * self.slice_size;
+            let end = min(start + self.slice_size, result.len());
+            result[start..end].copy_from_slice(&slice[..end - start]);
+        }
+
+        anyhow::ensure!(md5(&result) == self.file_hash, "Can't repair: repaired data has the wrong hash (PAR2)");
+
+        Ok(result)
+    }
+}
+
+fn invert(mut matrix: Vec<Vec<u16>>) -> Result<Vec<Vec<u16>>> {
+    let n = matrix.len();
+    let mut result: Vec<Vec<u16>> = (0..n)
+        .map(|i| (0..n).map(|j| (i == j) as u16).collect())
+        .collect();
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .find(|r| matrix[*r][col] != 0)
+            .ok_or_else(|| Error::msg("Can't repair: recovery slices are not independent (PAR2)"))?;
+        matrix.swap(col, pivot);
+        result.swap(col, pivot);
+
+        let p = matrix[col][col];
+        for j in 0..n {
+            matrix[col][j] = galois::div(matrix[col][j], p);
+            result[col][j] = galois::div(result[col][j], p);
+        }
+
+        for r in (0..n).filter(|r| *r != col) {
+            let f = matrix[r][col];
+            if f == 0 {
+                continue;
+            }
+            for j in 0..n {
+                matrix[r][j] ^= galois::mul(f, matrix[col][j]);
+                result[r][j] ^= galois::mul(f, result[col][j]);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn write_packet(out: &mut Vec<u8>, set_id: &[u8; 16], packet_type: &[u8; 16], body: &[u8]) {
+    let mut hashed = Vec::with_capacity(32 + body.len());
+    hashed.extend_from_slice(set_id);
+    hashed.extend_from_slice(packet_type);
+    hashed.extend_from_slice(body);
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&((HEADER_SIZE + body.len()) as u64).to_le_bytes());
+    out.extend_from_slice(&md5(&hashed));
+    out.extend_from_slice(&hashed);
+}
+
+/// Finds the valid packets in the data, returning their recovery set id, type and body.
+fn read_packets(data: &[u8]) -> Vec<([u8; 16], [u8; 16], &[u8])> {
+    let mut result = Vec::new();
+
+    let mut pos = 0;
+    while pos + HEADER_SIZE <= data.len() {
+        if &data[pos..pos + 8] != MAGIC {
+            pos += 4;
+            continue;
+        }
+
+        let len = u64::from_le_bytes(data[pos + 8..pos + 16].try_into().unwrap());
+        let valid = len >= HEADER_SIZE as u64
+            && len % 4 == 0
+            && pos as u64 + len <= data.len() as u64
+            && md5(&data[pos + 32..pos + len as usize]) == data[pos + 16..pos + 32];
+        if !valid {
+            pos += 4;
+            continue;
+        }
+
+        let len = len as usize;
+        result.push((
+            to_array(&data[pos + 32..pos + 48]),
+            to_array(&data[pos + 48..pos + 64]),
+            &data[pos + HEADER_SIZE..pos + len],
+        ));
+        pos += len;
+    }
+
+    result
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    to_array(&Md5::digest(data))
+}
+
+fn to_array(data: &[u8]) -> [u8; 16] {
+    data.try_into().unwrap()
+}
+
+fn pad4(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::par2::{create, Par2Config, RecoverySet, Verification};
+
+    fn data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 13 % 251) as u8).collect()
+    }
+
+    fn config() -> Par2Config {
+        Par2Config {
+            slice_size: 1024,
+            redundancy_percent: 10,
+        }
+    }
+
+    #[test]
+    fn intact_data_verifies() {
+        let orig = data(50_000);
+        let files = create("pack", &orig, &config()).unwrap();
+        let set = RecoverySet::parse(&[files[0].1.clone(), files[1].1.clone()]).unwrap();
+
+        assert_eq!(Verification::Intact, set.verify(&orig));
+    }
+
+    #[test]
+    fn repairs_damaged_slices() {
+        let orig = data(50_000);
+        let files = create("pack", &orig, &config()).unwrap();
+        let set = RecoverySet::parse(&[files[1].1.clone()]).unwrap();
+
+        let mut damaged = orig.clone();
+        damaged[0] ^= 1;
+        damaged[10_240..12_000].fill(0);
+        damaged[49_999] = 0;
+
+        assert_eq!(Verification::Damaged(4, 5), set.verify(&damaged));
+        assert_eq!(orig, set.repair(&damaged).unwrap());
+    }
+
+    #[test]
+    fn repairs_truncated_data() {
+        let orig = data(50_000);
+        let files = create("pack", &orig, &config()).unwrap();
+        let set = RecoverySet::parse(&[files[1].1.clone()]).unwrap();
+
+        assert_eq!(orig, set.repair(&orig[..47_000]).unwrap());
+    }
+
+    #[test]
+    fn fails_with_too_much_damage() {
+        let orig = data(50_000);
+        let files = create("pack", &orig, &config()).unwrap();
+        let set = RecoverySet::parse(&[files[1].1.clone()]).unwrap();
+
+        let mut damaged = orig.clone();
+        damaged[..10_000].fill(0);
+
+        assert!(set.repair(&damaged).is_err());
+    }
+
+    #[test]
+    fn ignores_damaged_packets() {
+        let orig = data(50_000);
+        let files = create("pack", &orig, &config()).unwrap();
+
+        let mut volume = files[1].1.clone();
+        volume[100] ^= 1;
+
+        let set = RecoverySet::parse(&[volume]).unwrap();
+
+        let mut damaged = orig.clone();
+        damaged[5_000] ^= 1;
+        assert_eq!(orig, set.repair(&damaged).unwrap());
+    }
+}
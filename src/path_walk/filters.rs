@@ -0,0 +1,178 @@
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use human_repr::HumanCount;
+use itertools::Itertools;
+
+use crate::path_walk::SkipReason;
+
+/// Marks folders with contents that can be recreated, like caches. See https://bford.info/cachedir/
+pub const CACHEDIR_TAG_NAME: &str = "CACHEDIR.TAG";
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Leave files out by their metadata. Each shared item has its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalkFilters {
+    /// Don't read folders with a valid `CACHEDIR.TAG` file. They are kept as empty folders.
+    pub skip_cache_dirs: bool,
+    pub max_size: Option<u64>,
+    /// Files modified less than this time ago are skipped.
+    pub min_age: Option<Duration>,
+    /// Files modified more than this time ago are skipped.
+    pub max_age: Option<Duration>,
+    /// Only files owned by one of these users are kept. Empty keeps all.
+    pub owners: Vec<u32>,
+}
+
+impl Default for WalkFilters {
+    fn default() -> Self {
+        WalkFilters {
+            skip_cache_dirs: true,
+            max_size: None,
+            min_age: None,
+            max_age: None,
+            owners: Vec::new(),
+        }
+    }
+}
+
+impl WalkFilters {
+    pub fn is_cache_dir(&self, path: &Path) -> bool {
+        if !self.skip_cache_dirs {
+            return false;
+        }
+
+        let Ok(file) = fs::File::open(path.join(CACHEDIR_TAG_NAME)) else {
+            return false;
+        };
+
+        let mut signature = Vec::new();
+        let result = file
+            .take(CACHEDIR_TAG_SIGNATURE.len() as u64)
+            .read_to_end(&mut signature);
+
+        result.is_ok() && signature == CACHEDIR_TAG_SIGNATURE
+    }
+
+    /// Why a file should be skipped, if it should.
+    pub fn check_file(&self, metadata: &fs::Metadata, now: SystemTime) -> Option<SkipReason> {
+        if self.max_size.is_some_and(|max| metadata.len() > max) {
+            return Some(SkipReason::TooLarge);
+        }
+
+        // Files from the future have age zero
+        let age = metadata
+            .modified()
+            .ok()
+            .map(|modified| now.duration_since(modified).unwrap_or_default());
+        if let Some(age) = age {
+            if self.min_age.is_some_and(|min| age < min) {
+                return Some(SkipReason::TooNew);
+            }
+            if self.max_age.is_some_and(|max| age > max) {
+                return Some(SkipReason::TooOld);
+            }
+        }
+
+        if !self.owners.is_empty() && owner(metadata).is_some_and(|uid| !self.owners.contains(&uid)) {
+            return Some(SkipReason::Owner);
+        }
+
+        None
+    }
+}
+
+/// Lists what is left out, e.g. "Skipping files larger than 1MB, folders with a CACHEDIR.TAG".
+impl fmt::Display for WalkFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut skipped = Vec::new();
+        if let Some(max) = self.max_size {
+            skipped.push(format!("files larger than {}", max.human_count_bytes()));
+        }
+        if let Some(min) = self.min_age {
+            skipped.push(format!("files modified less than {} ago", format_age(min)));
+        }
+        if let Some(max) = self.max_age {
+            skipped.push(format!("files modified more than {} ago", format_age(max)));
+        }
+        if !self.owners.is_empty() {
+            skipped.push(format!("files not owned by {}", self.owners.iter().join(", ")));
+        }
+        if self.skip_cache_dirs {
+            skipped.push(format!("folders with a {}", CACHEDIR_TAG_NAME));
+        }
+
+        match skipped.is_empty() {
+            true => write!(f, "Nothing is skipped"),
+            false => write!(f, "Skipping {}", skipped.join(", ")),
+        }
+    }
+}
+
+/// Whole days, hours, minutes and seconds, e.g. "2d 3h".
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let parts = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+
+    match parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .join(" ")
+    {
+        s if s.is_empty() => "0s".to_string(),
+        s => s,
+    }
+}
+
+#[cfg(unix)]
+fn owner(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::path_walk::filters::WalkFilters;
+
+    #[test]
+    fn displays_what_is_skipped() {
+        assert_eq!(
+            "Nothing is skipped",
+            WalkFilters {
+                skip_cache_dirs: false,
+                ..Default::default()
+            }
+            .to_string()
+        );
+
+        let filters = WalkFilters {
+            max_size: Some(10_000_000),
+            min_age: Some(Duration::from_secs(90)),
+            max_age: Some(Duration::from_secs(30 * 86400 + 3600)),
+            owners: vec![1000, 1001],
+            ..Default::default()
+        };
+        assert_eq!(
+            "Skipping files larger than 10MB, files modified less than 1m 30s ago, files modified more than 30d 1h ago, \
+             files not owned by 1000, 1001, folders with a CACHEDIR.TAG",
+            filters.to_string()
+        );
+    }
+}
@@ -0,0 +1,763 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::SystemTime;
+use std::{fmt, fs, io};
+
+use anyhow::{Error, Result};
+use flume::{Receiver, Sender};
+use relative_path::RelativePathBuf;
+
+pub use crate::path_walk::filters::{WalkFilters, CACHEDIR_TAG_NAME};
+use crate::path_walk::mounts::MountTable;
+use crate::path_walk::rules::DirRules;
+pub use crate::path_walk::rules::{PathRules, IGNORE_FILE_NAME};
+use crate::snapshot::attributes::PathAttributes;
+use crate::snapshot::kind::PathKind;
+
+mod filters;
+mod mounts;
+mod rules;
+
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    pub rules: PathRules,
+    /// Back up what symlinks point to instead of the links. Links that would create a cycle are kept as links.
+    pub follow_symlinks: bool,
+    /// Leave FIFOs, sockets and devices out, instead of storing their metadata.
+    pub skip_special_files: bool,
+    /// Don't enter other file systems mounted inside the root.
+    pub one_file_system: bool,
+    /// Types of file systems never entered, like `proc` or `tmpfs`.
+    pub skip_fs_types: Vec<String>,
+    pub filters: WalkFilters,
+    /// Read folders using this many threads. The paths are still found in the same order.
+    pub threads: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SkipReason {
+    Excluded,
+    SpecialFile,
+    /// A mount point, kept as an empty folder.
+    MountPoint,
+    /// A folder with a `CACHEDIR.TAG`, kept as an empty folder.
+    CacheDir,
+    TooLarge,
+    TooNew,
+    TooOld,
+    Owner,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Excluded => write!(f, "excluded"),
+            SkipReason::SpecialFile => write!(f, "special files skipped"),
+            SkipReason::MountPoint => write!(f, "mount points not entered"),
+            SkipReason::CacheDir => write!(f, "cache folders not entered"),
+            SkipReason::TooLarge => write!(f, "too large"),
+            SkipReason::TooNew => write!(f, "too new"),
+            SkipReason::TooOld => write!(f, "too old"),
+            SkipReason::Owner => write!(f, "skipped by owner"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WalkSummary {
+    /// Paths left out of the walk. The contents of skipped directories are not read, so they are not listed.
+    pub skipped: Vec<(RelativePathBuf, SkipReason)>,
+}
+
+/// What was read about a path that was found: its metadata and its attributes, or why they couldn't be read.
+pub type PathInfo = io::Result<(fs::Metadata, Result<PathAttributes>)>;
+
+pub fn path_walk(
+    root: PathBuf,
+    options: &WalkOptions,
+    mut cb: impl FnMut(PathBuf, RelativePathBuf, PathInfo),
+) -> Result<WalkSummary> {
+    let root_metadata = fs::metadata(&root)?;
+
+    if root_metadata.is_file() {
+        let relative = RelativePathBuf::from_path(".").unwrap().normalize();
+        let attributes = PathAttributes::read(&root, &root_metadata);
+        cb(root, relative, Ok((root_metadata, attributes)));
+
+        Ok(WalkSummary::default())
+    } else if root_metadata.is_dir() {
+        let mounts = match options.skip_fs_types.is_empty() {
+            true => MountTable::default(),
+            false => MountTable::load()?,
+        };
+
+        let scanner = Scanner {
+            root: &root,
+            options,
+            mounts,
+            now: SystemTime::now(),
+        };
+
+        let root_dir = QueuedDir {
+            path: root.clone(),
+            metadata: Some(root_metadata),
+            rules: DirRules::new(&options.rules)?,
+            ancestors: Vec::new(),
+            parent_device: None,
+        };
+
+        let mut summary = WalkSummary::default();
+
+        // The threads only read the folders. What they find is passed to the callback from here, in the same order
+        // as without them
+        let jobs = ScanJobs::new();
+        std::thread::scope(|s| {
+            // Stops the threads even if the callback panics, or the scope would wait for them forever
+            let _finish = FinishOnDrop(&jobs);
+
+            if options.threads > 1 {
+                for _ in 0..options.threads {
+                    s.spawn(|| jobs.run(&scanner));
+                }
+            }
+
+            let mut pending = vec![Pending::Queued(Box::new(root_dir))];
+            while let Some(next) = pending.pop() {
+                let found = match next {
+                    Pending::Queued(dir) => scanner.scan(*dir),
+                    Pending::Scanning(rx) => rx.recv().unwrap(),
+                };
+
+                for found in found {
+                    match found {
+                        Found::Path(path, relative, metadata) => cb(path, relative, metadata),
+                        Found::Skipped(relative, reason) => summary.skipped.push((relative, reason)),
+                        Found::Dir(dir) if options.threads > 1 => pending.push(Pending::Scanning(jobs.add(dir))),
+                        Found::Dir(dir) => pending.push(Pending::Queued(Box::new(dir))),
+                    }
+                }
+            }
+        });
+
+        Ok(summary)
+    } else {
+        Err(Error::msg(format!("should be a dir or a file (is {:?})", root_metadata.file_type())))
+    }
+}
+
+/// A directory waiting to be read.
+struct QueuedDir {
+    path: PathBuf,
+    metadata: Option<fs::Metadata>,
+    rules: DirRules,
+    /// The directories it is inside of, when following symlinks, to find cycles.
+    ancestors: Vec<FileId>,
+    /// The device of the directory it was found in. When it is different, this is a mount point.
+    parent_device: Option<u64>,
+}
+
+/// What reading a directory found, in the order it must be handled.
+enum Found {
+    Path(PathBuf, RelativePathBuf, PathInfo),
+    Skipped(RelativePathBuf, SkipReason),
+    Dir(QueuedDir),
+}
+
+impl Found {
+    /// The attributes are read here, with the metadata, so that is also done by the threads reading the folders.
+    fn path(path: PathBuf, relative: RelativePathBuf, metadata: io::Result<fs::Metadata>) -> Found {
+        let info = metadata.map(|metadata| {
+            let attributes = PathAttributes::read(&path, &metadata);
+            (metadata, attributes)
+        });
+
+        Found::Path(path, relative, info)
+    }
+}
+
+enum Pending {
+    Queued(Box<QueuedDir>),
+    /// Being read by one of the threads.
+    Scanning(Receiver<Vec<Found>>),
+}
+
+/// The directories waiting for the threads. The last ones are read first, because the walk needs them first.
+struct ScanJobs {
+    state: Mutex<ScanJobsState>,
+    available: Condvar,
+}
+
+struct ScanJobsState {
+    jobs: Vec<(QueuedDir, Sender<Vec<Found>>)>,
+    finished: bool,
+}
+
+impl ScanJobs {
+    fn new() -> ScanJobs {
+        ScanJobs {
+            state: Mutex::new(ScanJobsState {
+                jobs: Vec::new(),
+                finished: false,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    fn add(&self, dir: QueuedDir) -> Receiver<Vec<Found>> {
+        let (tx, rx) = flume::bounded(1);
+
+        self.state.lock().unwrap().jobs.push((dir, tx));
+        self.available.notify_one();
+
+        rx
+    }
+
+    /// Stops the threads once they are done with the directories they are reading. The ones waiting are dropped.
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.clear();
+        state.finished = true;
+        self.available.notify_all();
+    }
+
+    fn run(&self, scanner: &Scanner) {
+        loop {
+            let (dir, tx) = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    match state.jobs.pop() {
+                        Some(job) => break job,
+                        None if state.finished => return,
+                        None => state = self.available.wait(state).unwrap(),
+                    }
+                }
+            };
+
+            let _ = tx.send(scanner.scan(dir));
+        }
+    }
+}
+
+struct FinishOnDrop<'a>(&'a ScanJobs);
+
+impl Drop for FinishOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+/// Reads directories. Shared by all the threads of a walk.
+struct Scanner<'a> {
+    root: &'a Path,
+    options: &'a WalkOptions,
+    mounts: MountTable,
+    /// The ages of the files are from when the walk started.
+    now: SystemTime,
+}
+
+impl Scanner<'_> {
+    fn relative_path(&self, path: &Path) -> RelativePathBuf {
+        RelativePathBuf::from_path(path.strip_prefix(self.root).unwrap())
+            .unwrap()
+            .normalize()
+    }
+
+    fn scan(&self, dir: QueuedDir) -> Vec<Found> {
+        let mut result = Vec::new();
+        self.scan_into(dir, &mut result);
+        result
+    }
+
+    fn scan_into(&self, dir: QueuedDir, result: &mut Vec<Found>) {
+        let path = dir.path;
+        let relative = self.relative_path(&path);
+
+        macro_rules! unwrap {
+            ($f:expr) => {
+                match $f {
+                    Err(err) => {
+                        result.push(Found::path(path, relative, Err(err)));
+                        return;
+                    }
+                    Ok(e) => e,
+                }
+            };
+        }
+
+        let path_metadata = match dir.metadata {
+            Some(metadata) => metadata,
+            None => unwrap!(fs::symlink_metadata(&path)),
+        };
+
+        let device = file_id(&path_metadata).map(|(device, _)| device);
+        if dir.parent_device.is_some() && device != dir.parent_device && !self.enters_mount_point(&path) {
+            result.push(Found::Skipped(relative.clone(), SkipReason::MountPoint));
+            result.push(Found::path(path, relative, Ok(path_metadata)));
+            return;
+        }
+
+        if path != self.root && self.options.filters.is_cache_dir(&path) {
+            result.push(Found::Skipped(relative.clone(), SkipReason::CacheDir));
+            result.push(Found::path(path, relative, Ok(path_metadata)));
+            return;
+        }
+
+        // The rules of the directory apply to its entries, so they are known before reading it
+        let rules = unwrap!(dir.rules.enter(&path, &relative).map_err(io::Error::other));
+
+        let mut ancestors = dir.ancestors;
+        if self.options.follow_symlinks {
+            ancestors.extend(file_id(&path_metadata));
+        }
+
+        let mut found = Vec::new();
+
+        for entry in unwrap!(fs::read_dir(&path)) {
+            let entry = unwrap!(entry);
+            let entry_path = entry.path();
+
+            if entry_path.starts_with("c:\\Windows") {
+                continue;
+            }
+
+            let entry_relative = self.relative_path(&entry_path);
+
+            match entry.file_type() {
+                Err(err) => {
+                    found.push((entry_path, entry_relative, Some(err)));
+                }
+                Ok(file_type) if rules.is_excluded(&entry_relative, file_type.is_dir()) => {
+                    result.push(Found::Skipped(entry_relative, SkipReason::Excluded));
+                }
+                Ok(file_type) if file_type.is_dir() => {
+                    result.push(Found::Dir(QueuedDir {
+                        path: entry_path,
+                        metadata: None,
+                        rules: rules.clone(),
+                        ancestors: ancestors.clone(),
+                        parent_device: device,
+                    }));
+                }
+                _ => {
+                    found.push((entry_path, entry_relative, None));
+                }
+            };
+        }
+
+        result.push(Found::path(path, relative, Ok(path_metadata)));
+
+        for (entry_path, entry_relative, entry_err) in found {
+            if let Some(err) = entry_err {
+                result.push(Found::path(entry_path, entry_relative, Err(err)));
+                continue;
+            }
+
+            match fs::symlink_metadata(&entry_path) {
+                Err(err) => {
+                    result.push(Found::path(entry_path, entry_relative, Err(err)));
+                }
+                Ok(entry_metadata) if entry_metadata.is_symlink() && self.options.follow_symlinks => {
+                    let link = (entry_path, entry_relative, entry_metadata);
+                    self.follow_symlink(link, &rules, &ancestors, device, result);
+                }
+                // It was replaced by a folder after the listing
+                Ok(entry_metadata) if entry_metadata.is_dir() => {
+                    result.push(Found::Dir(QueuedDir {
+                        path: entry_path,
+                        metadata: Some(entry_metadata),
+                        rules: rules.clone(),
+                        ancestors: ancestors.clone(),
+                        parent_device: device,
+                    }));
+                }
+                Ok(entry_metadata) if PathKind::from_metadata(&entry_metadata).is_special() => {
+                    if self.options.skip_special_files {
+                        result.push(Found::Skipped(entry_relative, SkipReason::SpecialFile));
+                    } else {
+                        result.push(Found::path(entry_path, entry_relative, Ok(entry_metadata)));
+                    }
+                }
+                Ok(entry_metadata) => {
+                    result.push(self.check_file(entry_path, entry_relative, entry_metadata));
+                }
+            };
+        }
+    }
+
+    /// Skips a file if the filters say so.
+    fn check_file(&self, path: PathBuf, relative: RelativePathBuf, metadata: fs::Metadata) -> Found {
+        let reason = match metadata.is_file() {
+            true => self.options.filters.check_file(&metadata, self.now),
+            false => None,
+        };
+
+        match reason {
+            Some(reason) => Found::Skipped(relative, reason),
+            None => Found::path(path, relative, Ok(metadata)),
+        }
+    }
+
+    fn enters_mount_point(&self, path: &Path) -> bool {
+        if self.options.one_file_system {
+            return false;
+        }
+
+        match self.mounts.get_fs_type(path) {
+            Some(fs_type) => !self.options.skip_fs_types.iter().any(|t| t == fs_type),
+            None => true,
+        }
+    }
+
+    /// Walks what the link points to as if it was in its place. Links that can't be followed are kept as links: the
+    /// ones that point to nothing or to special files, and the ones to a folder they are inside of.
+    fn follow_symlink(
+        &self,
+        (path, relative, link_metadata): (PathBuf, RelativePathBuf, fs::Metadata),
+        rules: &DirRules,
+        ancestors: &[FileId],
+        parent_device: Option<u64>,
+        result: &mut Vec<Found>,
+    ) {
+        match fs::metadata(&path) {
+            Ok(target) if target.is_file() => {
+                result.push(self.check_file(path, relative, target));
+            }
+            Ok(target) if target.is_dir() => {
+                if rules.is_excluded(&relative, true) {
+                    result.push(Found::Skipped(relative, SkipReason::Excluded));
+                    return;
+                }
+
+                match file_id(&target) {
+                    Some(id) if !ancestors.contains(&id) => {
+                        result.push(Found::Dir(QueuedDir {
+                            path,
+                            metadata: Some(target),
+                            rules: rules.clone(),
+                            ancestors: ancestors.to_vec(),
+                            parent_device,
+                        }));
+                    }
+                    _ => result.push(Found::path(path, relative, Ok(link_metadata))),
+                }
+            }
+            _ => {
+                result.push(Found::path(path, relative, Ok(link_metadata)));
+            }
+        }
+    }
+}
+
+/// Identifies a file or directory, however it is reached.
+pub type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without an id, cycles can't be found, so links to folders are never followed.
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+/// The id of a file with other hard links to it, so its data is read only once.
+#[cfg(unix)]
+pub fn hard_link_id(metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    match metadata.is_file() && metadata.nlink() > 1 {
+        true => file_id(metadata),
+        false => None,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hard_link_id(_metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use relative_path::RelativePathBuf;
+
+    use crate::path_walk::{path_walk, SkipReason, WalkOptions, IGNORE_FILE_NAME};
+
+    #[test]
+    fn skips_excluded_paths() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("app/node_modules/lib")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("app/node_modules/lib/index.js"), "").unwrap();
+        std::fs::write(dir.join("app/main.js"), "").unwrap();
+        std::fs::write(dir.join("app/debug.log"), "").unwrap();
+        std::fs::write(dir.join("app").join(IGNORE_FILE_NAME), "node_modules/\n").unwrap();
+
+        let mut options = WalkOptions::default();
+        options.rules.add_exclude("*.log").unwrap();
+        options.rules.add_exclude("/target").unwrap();
+
+        let mut paths = Vec::new();
+        let summary = path_walk(dir.to_path_buf(), &options, |_, relative, info| {
+            info.unwrap().1.unwrap();
+            paths.push(relative.to_string());
+        })
+        .unwrap();
+
+        paths.sort();
+        assert_eq!(vec!["", "app", "app/.mfsbignore", "app/main.js"], paths);
+
+        let mut skipped = summary.skipped;
+        skipped.sort();
+        let expected: Vec<_> = ["app/debug.log", "app/node_modules", "target"]
+            .iter()
+            .map(|p| (RelativePathBuf::from(p), SkipReason::Excluded))
+            .collect();
+        assert_eq!(expected, skipped);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_symlinks_or_follows_them_without_cycles() {
+        use std::os::unix::fs::symlink;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/file"), "data").unwrap();
+        symlink("a", dir.join("to_a")).unwrap();
+        symlink("..", dir.join("a/up")).unwrap();
+        symlink("a/file", dir.join("to_file")).unwrap();
+        symlink("nowhere", dir.join("dangling")).unwrap();
+
+        let walk = |follow_symlinks| {
+            let options = WalkOptions {
+                follow_symlinks,
+                ..Default::default()
+            };
+
+            let mut paths = Vec::new();
+            path_walk(dir.to_path_buf(), &options, |_, relative, info| {
+                let (metadata, _) = info.unwrap();
+                let kind = if metadata.is_symlink() {
+                    "link"
+                } else if metadata.is_dir() {
+                    "dir"
+                } else {
+                    "file"
+                };
+                paths.push(format!("{} {}", relative, kind));
+            })
+            .unwrap();
+
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(
+            vec![
+                " dir",
+                "a dir",
+                "a/file file",
+                "a/up link",
+                "dangling link",
+                "to_a link",
+                "to_file link"
+            ],
+            walk(false)
+        );
+        assert_eq!(
+            vec![
+                " dir",
+                "a dir",
+                "a/file file",
+                "a/up link",
+                "dangling link",
+                "to_a dir",
+                "to_a/file file",
+                "to_a/up link",
+                "to_file file"
+            ],
+            walk(true)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stores_or_skips_special_files() {
+        use std::ffi::CString;
+        use std::os::unix::net::UnixListener;
+
+        use crate::snapshot::kind::PathKind;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let fifo = CString::new(dir.join("fifo").to_str().unwrap()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) });
+        let _socket = UnixListener::bind(dir.join("socket")).unwrap();
+
+        let walk = |skip_special_files| {
+            let options = WalkOptions {
+                skip_special_files,
+                ..Default::default()
+            };
+
+            let mut paths = Vec::new();
+            let summary = path_walk(dir.to_path_buf(), &options, |_, relative, info| {
+                paths.push((relative.to_string(), PathKind::from_metadata(&info.unwrap().0)));
+            })
+            .unwrap();
+
+            paths.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut skipped = summary.skipped;
+            skipped.sort();
+            (paths, skipped)
+        };
+
+        let (paths, skipped) = walk(false);
+        assert_eq!(
+            vec![
+                ("".to_string(), PathKind::Dir),
+                ("fifo".to_string(), PathKind::Fifo),
+                ("socket".to_string(), PathKind::Socket)
+            ],
+            paths
+        );
+        assert!(skipped.is_empty());
+
+        let (paths, skipped) = walk(true);
+        assert_eq!(vec![("".to_string(), PathKind::Dir)], paths);
+        assert_eq!(
+            vec![
+                (RelativePathBuf::from("fifo"), SkipReason::SpecialFile),
+                (RelativePathBuf::from("socket"), SkipReason::SpecialFile)
+            ],
+            skipped
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_files_with_hard_links() {
+        use crate::path_walk::hard_link_id;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a"), "data").unwrap();
+        std::fs::write(dir.join("single"), "data").unwrap();
+        std::fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+
+        let id = |name| hard_link_id(&std::fs::symlink_metadata(dir.join(name)).unwrap());
+
+        assert!(id("a").is_some());
+        assert_eq!(id("a"), id("b"));
+        assert_eq!(None, id("single"));
+    }
+
+    #[test]
+    fn skips_cache_dirs_and_filtered_files() {
+        use std::time::{Duration, SystemTime};
+
+        use crate::path_walk::{WalkFilters, CACHEDIR_TAG_NAME};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("cache/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("fake")).unwrap();
+        std::fs::write(
+            dir.join("cache").join(CACHEDIR_TAG_NAME),
+            "Signature: 8a477f597d28d172789f06886806bc55\n# a cache\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("fake").join(CACHEDIR_TAG_NAME), "Signature: none").unwrap();
+        std::fs::write(dir.join("large"), vec![1u8; 2000]).unwrap();
+        std::fs::write(dir.join("small"), "data").unwrap();
+        std::fs::write(dir.join("old"), "data").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("old"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+
+        let options = WalkOptions {
+            filters: WalkFilters {
+                max_size: Some(1000),
+                max_age: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut paths = Vec::new();
+        let summary =
+            path_walk(dir.to_path_buf(), &options, |_, relative, _| paths.push(relative.to_string())).unwrap();
+
+        paths.sort();
+        assert_eq!(vec!["", "cache", "fake", "fake/CACHEDIR.TAG", "small"], paths);
+
+        let mut skipped = summary.skipped;
+        skipped.sort();
+        assert_eq!(
+            vec![
+                (RelativePathBuf::from("cache"), SkipReason::CacheDir),
+                (RelativePathBuf::from("large"), SkipReason::TooLarge),
+                (RelativePathBuf::from("old"), SkipReason::TooOld),
+            ],
+            skipped
+        );
+    }
+
+    #[test]
+    fn walks_in_the_same_order_with_threads() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for i in 0..20 {
+            for j in 0..5 {
+                let sub = dir.join(format!("d{}/e{}", i, j));
+                std::fs::create_dir_all(&sub).unwrap();
+                std::fs::write(sub.join("file"), "").unwrap();
+            }
+        }
+
+        let walk = |threads| {
+            let options = WalkOptions {
+                threads,
+                ..Default::default()
+            };
+
+            let mut paths = Vec::new();
+            path_walk(dir.to_path_buf(), &options, |_, relative, _| paths.push(relative)).unwrap();
+            paths
+        };
+
+        let expected = walk(1);
+        assert_eq!(1 + 20 + 20 * 5 * 2, expected.len());
+        assert_eq!(expected, walk(8));
+    }
+
+    #[test]
+    fn stops_the_threads_if_the_callback_panics() {
+        let temp = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            std::fs::create_dir_all(temp.path().join(format!("d{}/e", i))).unwrap();
+        }
+
+        let root = temp.path().to_path_buf();
+        let (tx, rx) = flume::bounded(1);
+        std::thread::spawn(move || {
+            let options = WalkOptions {
+                threads: 4,
+                ..Default::default()
+            };
+            let result = std::panic::catch_unwind(|| path_walk(root, &options, |_, _, _| panic!("callback failed")));
+            tx.send(result.is_err()).unwrap();
+        });
+
+        assert!(rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap());
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// The mounted file systems, to know the type of the ones found during the walk.
+#[derive(Debug, Default)]
+pub(crate) struct MountTable {
+    /// Mount points and their file system types, in mount order.
+    mounts: Vec<(PathBuf, String)>,
+}
+
+impl MountTable {
+    /// Reads the mounts of the current process. Only Linux is supported: elsewhere the table is empty.
+    #[cfg(target_os = "linux")]
+    pub fn load() -> Result<MountTable> {
+        use anyhow::Context;
+
+        let text = std::fs::read_to_string("/proc/self/mounts").context("error reading the mounted file systems")?;
+        Ok(Self::parse(&text))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn load() -> Result<MountTable> {
+        Ok(MountTable::default())
+    }
+
+    /// Parses the `/proc/self/mounts` format: one mount per line, with the mount point and the type in the second
+    /// and third fields. Spaces and other special characters are escaped in octal, like `\040`.
+    fn parse(text: &str) -> MountTable {
+        let mounts = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().skip(1);
+                let mount_point = unescape(fields.next()?);
+                let fs_type = fields.next()?.to_string();
+                Some((PathBuf::from(mount_point), fs_type))
+            })
+            .collect();
+
+        MountTable { mounts }
+    }
+
+    /// The type of the file system mounted at `path`. Later mounts on the same path hide the previous ones.
+    pub fn get_fs_type(&self, path: &Path) -> Option<&str> {
+        let path = std::fs::canonicalize(path).ok()?;
+
+        self.mounts
+            .iter()
+            .rev()
+            .find(|(mount_point, _)| *mount_point == path)
+            .map(|(_, fs_type)| fs_type.as_str())
+    }
+}
+
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|o| o.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match (bytes[i], octal) {
+            (b'\\', Some(octal)) => {
+                result.push(octal.iter().fold(0u32, |v, b| (v << 3) | (b - b'0') as u32) as u8);
+                i += 4;
+            }
+            (b, _) => {
+                result.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::path_walk::mounts::MountTable;
+
+    #[test]
+    fn parses_mounts() {
+        let table = MountTable::parse(
+            "/dev/vda / ext4 rw,relatime 0 0\n\
+             proc /proc proc rw 0 0\n\
+             /dev/sdb1 /mnt/my\\040disk vfat rw 0 0\n\
+             tmpfs /proc tmpfs rw 0 0\n",
+        );
+
+        assert_eq!(
+            vec![
+                (PathBuf::from("/"), "ext4".to_string()),
+                (PathBuf::from("/proc"), "proc".to_string()),
+                (PathBuf::from("/mnt/my disk"), "vfat".to_string()),
+                (PathBuf::from("/proc"), "tmpfs".to_string()),
+            ],
+            table.mounts
+        );
+    }
+}
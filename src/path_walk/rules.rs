@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// Directories with a file with this name have their own rules, for the paths inside them.
+pub const IGNORE_FILE_NAME: &str = ".mfsbignore";
+
+/// Gitignore-style rules that select the paths to back up. Excludes are plain patterns and includes are negated ones,
+/// like `!*.log`. Inside a rule set, the last matching rule wins. Between sets, the command line wins over the ignore
+/// files found in the folders, deeper ones first, and those win over the config.
+#[derive(Clone, Debug, Default)]
+pub struct PathRules {
+    config: Vec<String>,
+    cli: Vec<String>,
+}
+
+impl PathRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the rules of a config file, with the same format as the ignore files.
+    pub fn add_config_file(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("error reading {}", path.display()))?;
+
+        self.config.extend(text.lines().map(|l| l.to_string()));
+
+        build(&self.config)?;
+        Ok(())
+    }
+
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<()> {
+        self.add_cli(pattern.to_string())
+    }
+
+    pub fn add_include(&mut self, pattern: &str) -> Result<()> {
+        self.add_cli(format!("!{}", pattern))
+    }
+
+    fn add_cli(&mut self, rule: String) -> Result<()> {
+        self.cli.push(rule);
+
+        build(&self.cli)?;
+        Ok(())
+    }
+}
+
+fn build(rules: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(".");
+    for rule in rules {
+        builder
+            .add_line(None, rule)
+            .with_context(|| format!("invalid rule: {}", rule))?;
+    }
+
+    Ok(builder.build()?)
+}
+
+/// The rules that apply to the entries of a directory.
+#[derive(Clone)]
+pub(crate) struct DirRules {
+    cli: Arc<Gitignore>,
+    config: Arc<Gitignore>,
+    /// The ignore files of the directory and its parents, by the relative path of their directory.
+    files: Vec<(RelativePathBuf, Arc<Gitignore>)>,
+}
+
+impl DirRules {
+    pub fn new(rules: &PathRules) -> Result<DirRules> {
+        Ok(DirRules {
+            cli: Arc::new(build(&rules.cli)?),
+            config: Arc::new(build(&rules.config)?),
+            files: Vec::new(),
+        })
+    }
+
+    /// The rules for the entries of a directory, including its ignore file if it has one.
+    pub fn enter(&self, path: &Path, relative_path: &RelativePath) -> Result<DirRules> {
+        let file = path.join(IGNORE_FILE_NAME);
+        if !file.is_file() {
+            return Ok(self.clone());
+        }
+
+        let mut builder = GitignoreBuilder::new(".");
+        let text = fs::read_to_string(&file).with_context(|| format!("error reading {}", file.display()))?;
+        for line in text.lines() {
+            builder
+                .add_line(Some(file.clone()), line)
+                .with_context(|| format!("invalid rule in {}: {}", file.display(), line))?;
+        }
+
+        let mut result = self.clone();
+        result
+            .files
+            .push((relative_path.to_owned(), Arc::new(builder.build()?)));
+        Ok(result)
+    }
+
+    pub fn is_excluded(&self, relative_path: &RelativePath, is_dir: bool) -> bool {
+        let check = |rules: &Gitignore, path: &RelativePath| match rules.matched(path.to_path(""), is_dir) {
+            Match::None => None,
+            Match::Ignore(_) => Some(true),
+            Match::Whitelist(_) => Some(false),
+        };
+
+        check(&self.cli, relative_path)
+            .or_else(|| {
+                self.files.iter().rev().find_map(|(dir, rules)| {
+                    let path = relative_path.strip_prefix(dir).ok()?;
+                    check(rules, path)
+                })
+            })
+            .or_else(|| check(&self.config, relative_path))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relative_path::RelativePath;
+
+    use crate::path_walk::rules::{DirRules, PathRules, IGNORE_FILE_NAME};
+
+    #[test]
+    fn applies_rules_by_precedence() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("config"), "*.log\ntarget/\n").unwrap();
+        std::fs::write(dir.join("src").join(IGNORE_FILE_NAME), "!keep.log\n/generated\n").unwrap();
+
+        let mut rules = PathRules::new();
+        rules.add_config_file(&dir.join("config")).unwrap();
+        rules.add_include("target/").unwrap();
+        rules.add_exclude("node_modules").unwrap();
+
+        let root = DirRules::new(&rules).unwrap();
+        let src = root
+            .enter(&dir.join("src"), RelativePath::new("src"))
+            .unwrap();
+
+        let excluded = |rules: &DirRules, path: &str, is_dir: bool| rules.is_excluded(RelativePath::new(path), is_dir);
+
+        assert!(excluded(&root, "a.log", false));
+        assert!(excluded(&root, "x/node_modules", true));
+        assert!(!excluded(&root, "target", true));
+        assert!(!excluded(&root, "a.txt", false));
+
+        assert!(excluded(&src, "src/b.log", false));
+        assert!(!excluded(&src, "src/keep.log", false));
+        assert!(excluded(&src, "src/generated", true));
+        assert!(!excluded(&src, "src/x/generated", true));
+    }
+}
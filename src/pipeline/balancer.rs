@@ -0,0 +1,61 @@
+use std::cmp::max;
+use std::time::Duration;
+
+use crate::compress::Bottleneck;
+use crate::pipeline::monitor::{PipelineStepStats, PipelineStepTimes};
+
+/// Number of stored packs between two decisions, so one slow pack does not swing the level.
+const PACKS_PER_DECISION: u64 = 4;
+
+/// Finds out if compressing or storing packs is holding the pipeline back, by comparing how long the compression
+/// threads wait to hand packs over with how long the store thread waits to get them.
+pub struct ThroughputBalancer {
+    compress: PipelineStepStats,
+    store: PipelineStepStats,
+    last_compress: PipelineStepTimes,
+    last_store: PipelineStepTimes,
+}
+
+impl ThroughputBalancer {
+    pub fn new(compress: PipelineStepStats, store: PipelineStepStats) -> Self {
+        Self {
+            compress,
+            store,
+            last_compress: PipelineStepTimes::default(),
+            last_store: PipelineStepTimes::default(),
+        }
+    }
+
+    pub fn find_bottleneck(&mut self) -> Option<Bottleneck> {
+        let compress_now = self.compress.get_times();
+        let store_now = self.store.get_times();
+
+        let compress = compress_now.since(&self.last_compress);
+        let store = store_now.since(&self.last_store);
+        if store.count < PACKS_PER_DECISION {
+            return None;
+        }
+
+        self.last_compress = compress_now;
+        self.last_store = store_now;
+
+        decide(&compress, &store)
+    }
+}
+
+fn decide(compress: &PipelineStepTimes, store: &PipelineStepTimes) -> Option<Bottleneck> {
+    let waiting_for_store = per_pack(compress.send, compress.count);
+    let waiting_for_compress = per_pack(store.recv, store.count);
+
+    if waiting_for_store > waiting_for_compress * 2 {
+        Some(Bottleneck::Storage)
+    } else if waiting_for_compress > waiting_for_store * 2 {
+        Some(Bottleneck::Compression)
+    } else {
+        None
+    }
+}
+
+fn per_pack(time: Duration, count: u64) -> Duration {
+    time / max(count, 1) as u32
+}
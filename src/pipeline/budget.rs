@@ -0,0 +1,180 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use human_repr::HumanCount;
+use indicatif::ProgressBar;
+use quanta::{Clock, Instant};
+
+const UPDATE_PROGRESS_FREQUENCY: Duration = Duration::from_millis(300);
+
+/// Limits the bytes of file data in flight in the pipeline. Only the chunkers wait for memory to be available: the
+/// steps after them just move or resize the reservations they receive, so they can always finish what was started and
+/// free it.
+pub struct MemoryBudget {
+    limit: u64,
+    state: Mutex<BudgetState>,
+    available: Condvar,
+    clock: Clock,
+    pg_bar: Option<ProgressBar>,
+}
+
+struct BudgetState {
+    used: u64,
+    peak: u64,
+    last_update: Instant,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64, pg_bar: Option<ProgressBar>) -> Arc<MemoryBudget> {
+        let clock = Clock::new();
+
+        Arc::new(MemoryBudget {
+            limit,
+            state: Mutex::new(BudgetState {
+                used: 0,
+                peak: 0,
+                last_update: clock.now() - UPDATE_PROGRESS_FREQUENCY,
+            }),
+            available: Condvar::new(),
+            clock,
+            pg_bar,
+        })
+    }
+
+    pub fn get_limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn get_used(&self) -> u64 {
+        self.state.lock().unwrap().used
+    }
+
+    pub fn get_peak(&self) -> u64 {
+        self.state.lock().unwrap().peak
+    }
+
+    /// Waits until `bytes` fit in the budget. A request bigger than the whole budget is let through when nothing else
+    /// is in use, so it can't block forever.
+    pub fn acquire(self: &Arc<Self>, bytes: u64) -> MemoryReservation {
+        let mut state = self.state.lock().unwrap();
+        while state.used > 0 && state.used + bytes > self.limit {
+            state = self.available.wait(state).unwrap();
+        }
+
+        self.change(&mut state, bytes as i64);
+
+        MemoryReservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    /// An empty reservation, that grows as others are merged into it.
+    pub fn empty(self: &Arc<Self>) -> MemoryReservation {
+        MemoryReservation {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    fn change(&self, state: &mut BudgetState, delta: i64) {
+        state.used = state.used.saturating_add_signed(delta);
+        state.peak = state.peak.max(state.used);
+
+        if delta < 0 {
+            self.available.notify_all();
+        }
+
+        self.update_progress(state);
+    }
+
+    fn update_progress(&self, state: &mut BudgetState) {
+        let Some(pg_bar) = &self.pg_bar else {
+            return;
+        };
+
+        let now = self.clock.now();
+        if now - state.last_update < UPDATE_PROGRESS_FREQUENCY && state.used > 0 {
+            return;
+        }
+        state.last_update = now;
+
+        pg_bar.set_message(format!(
+            "{:>9} of {:>9} (peak {})",
+            state.used.human_count_bytes().to_string(),
+            self.limit.human_count_bytes().to_string(),
+            state.peak.human_count_bytes()
+        ));
+        pg_bar.tick();
+    }
+}
+
+/// Bytes taken from a `MemoryBudget`, given back when dropped.
+pub struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+}
+
+impl MemoryReservation {
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Takes over the bytes of another reservation.
+    pub fn merge(&mut self, mut other: MemoryReservation) {
+        self.bytes += other.bytes;
+        other.bytes = 0;
+    }
+
+    /// Changes the reserved size to follow the data it accounts for. Never waits, even if the budget is exceeded.
+    pub fn resize(&mut self, bytes: u64) {
+        let mut state = self.budget.state.lock().unwrap();
+        self.budget
+            .change(&mut state, bytes as i64 - self.bytes as i64);
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.resize(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::pipeline::budget::MemoryBudget;
+
+    #[test]
+    fn acquire_waits_for_memory_to_be_freed() {
+        let budget = MemoryBudget::new(100, None);
+
+        let first = budget.acquire(60);
+        let mut pack = budget.empty();
+        pack.merge(first);
+        assert_eq!(60, budget.get_used());
+
+        let waiting = thread::spawn({
+            let budget = budget.clone();
+            move || budget.acquire(50).get_bytes()
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+
+        pack.resize(30);
+        assert_eq!(50, waiting.join().unwrap());
+
+        drop(pack);
+        assert_eq!(0, budget.get_used());
+        assert_eq!(80, budget.get_peak());
+
+        // Bigger than the budget, but nothing else is using it
+        assert_eq!(500, budget.acquire(500).get_bytes());
+    }
+}
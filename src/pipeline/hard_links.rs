@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use relative_path::RelativePathBuf;
+
+use crate::snapshot::builder::PathBuilder;
+
+/// The paths of a file with several hard links. Its data is read from only one of them, and the others link to that
+/// one. If it can't be read, the next path is read instead, so the others never link to a path without data.
+pub struct HardLinkGroup {
+    state: Mutex<GroupState>,
+}
+
+#[derive(Default)]
+struct GroupState {
+    /// The path the data was read from.
+    first: Option<RelativePathBuf>,
+    reading: bool,
+    waiting: VecDeque<Arc<PathBuilder>>,
+}
+
+impl HardLinkGroup {
+    pub fn new() -> Arc<HardLinkGroup> {
+        Arc::new(HardLinkGroup {
+            state: Mutex::new(GroupState::default()),
+        })
+    }
+
+    /// Adds a path of the file. Returns true if its data has to be read, because no other path was read or is being
+    /// read. Otherwise it is linked to the path that was read, now or once it is.
+    pub fn add(&self, path: &Arc<PathBuilder>) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(first) = &state.first {
+            path.set_hard_link(first.clone());
+            path.set_finished_adding_chunks(0);
+            false
+        } else if state.reading {
+            state.waiting.push_back(path.clone());
+            false
+        } else {
+            state.reading = true;
+            true
+        }
+    }
+
+    /// Called once `path` was read, or failed to. Returns the next path to read, if it failed.
+    pub fn finish_reading(&self, path: &PathBuilder, read: bool) -> Option<Arc<PathBuilder>> {
+        let mut state = self.state.lock().unwrap();
+
+        if !read {
+            let next = state.waiting.pop_front();
+            state.reading = next.is_some();
+            return next;
+        }
+
+        let first = path.get_relative_path().to_relative_path_buf();
+        for waiting in state.waiting.drain(..) {
+            waiting.set_hard_link(first.clone());
+            waiting.set_finished_adding_chunks(0);
+        }
+        state.first = Some(first);
+        state.reading = false;
+
+        None
+    }
+}
@@ -1,45 +1,112 @@
 use std::cmp::max;
+use std::collections::HashMap;
+use std::fs;
 use std::sync::Arc;
 
 use anyhow::Error;
 use anyhow::Result;
 use flume::{Receiver, Sender};
 
-use crate::chunk::Chunker;
-use crate::compress::Compressor;
+use crate::chunk::{ChunkData, Chunker};
+use crate::compress::{CompressionType, Compressor};
+use crate::delta::DeltaCompressor;
 use crate::ecc::ECC;
 use crate::encrypt::Encryptor;
 use crate::hash::Hasher;
 use crate::pack::builder::PackBuilder;
-use crate::path_walk::path_walk;
+use crate::pack::header::PackHeader;
+use crate::pack::index::ChunkIndex;
+use crate::pack::PackLocation;
+use crate::path_walk::{hard_link_id, path_walk, WalkSummary};
+use crate::pipeline::balancer::ThroughputBalancer;
+use crate::pipeline::budget::MemoryBudget;
+use crate::pipeline::hard_links::HardLinkGroup;
 use crate::pipeline::monitor::PipelineMonitor;
-use crate::snapshot::builder::SnapshotBuilder;
-
+use crate::pipeline::threads::ThreadPool;
+use crate::repository::parity::{ParityConfig, ParityGroupBuilder};
+use crate::repository::Repository;
+use crate::snapshot::attributes::PathAttributes;
+use crate::snapshot::builder::{ChangeCheck, SnapshotBuilder};
+use crate::snapshot::source::SnapshotSource;
+
+mod balancer;
+pub mod budget;
+mod hard_links;
 pub mod monitor;
+mod threads;
+
+#[derive(Clone, Debug, Default)]
+pub struct PipelineOptions {
+    /// Threads that read and chunk files, one file at a time each. 0 uses a quarter of the available cores.
+    pub chunk_threads: u8,
+    /// Threads that compress, encrypt and add ECC to packs. 0 uses a quarter of the available cores.
+    pub prepare_threads: u8,
+    /// Maximum bytes of file data in flight between reading and storing it. 0 uses 1 GiB.
+    pub memory_budget: u64,
+    /// The name of the compressor used for packs. None uses Snappy.
+    pub compressor: Option<String>,
+    pub changed_files: ChangedFilePolicy,
+    /// Called before each attempt to read a file from the file system.
+    #[cfg(test)]
+    pub before_read: Option<tests::ReadHook>,
+}
+
+/// What to do with files that changed while they were read, so their data may be torn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChangedFilePolicy {
+    /// Keep what was read, flagged as changed.
+    #[default]
+    Keep,
+    /// Read it again, up to this many times. If it keeps changing, it is kept and flagged.
+    Retry(u32),
+    Fail,
+}
 
 pub struct Pipeline {
     monitor: PipelineMonitor,
+    index: Arc<ChunkIndex>,
 }
 
 impl Pipeline {
-    pub fn new(mut threads: u8) -> (Pipeline, Sender<Arc<SnapshotBuilder>>, Receiver<Arc<SnapshotBuilder>>) {
-        if threads == 0 {
-            threads = max(std::thread::available_parallelism().unwrap().get() / 4, 1) as u8;
-        }
+    pub fn new(
+        options: PipelineOptions,
+        repository: Arc<Repository>,
+    ) -> (Pipeline, Sender<Arc<SnapshotBuilder>>, Receiver<Arc<SnapshotBuilder>>) {
+        let default_threads = max(std::thread::available_parallelism().unwrap().get() / 4, 1) as u8;
+        let or_default = |threads: u8| if threads == 0 { default_threads } else { threads };
 
         let pack_size = 20 * 1024 * 1024;
-        let hasher = Hasher::build_by_name("Blake3").unwrap();
-        let chunker = Chunker::build_by_name("Rabin64 (mmap)", 1 * 1024 * 1024).unwrap();
-        let prepare_threads = threads;
-        let compressor = Compressor::build_by_name("Snappy").unwrap();
-        let encryptor = Encryptor::build_by_name("ChaCha20Poly1305", "1234").unwrap();
-        let ecc = ECC::build_by_name("SECDED").unwrap();
+        let index = ChunkIndex::new();
         let mut monitor = PipelineMonitor::new();
+        // The pack being built must fit with the chunks that complete it
+        let memory_budget = match options.memory_budget {
+            0 => 1024 * 1024 * 1024,
+            budget => max(budget, 4 * pack_size as u64),
+        };
+
+        let components = PipelineComponents {
+            pack_size,
+            hasher: Hasher::build_by_name("Blake3").unwrap(),
+            chunker: Chunker::build_by_name("Rabin64", 1 * 1024 * 1024).unwrap(),
+            index: index.clone(),
+            delta_cache_size: 64 * 1024 * 1024,
+            chunk_threads: or_default(options.chunk_threads),
+            prepare_threads: or_default(options.prepare_threads),
+            budget: monitor.create_memory_budget(memory_budget),
+            compressor: Compressor::build_by_name(options.compressor.as_deref().unwrap_or("Snappy")).unwrap(),
+            encryptor: Encryptor::build_by_name("ChaCha20Poly1305", "1234").unwrap(),
+            ecc: ECC::build_by_name("SECDED").unwrap(),
+            parity: repository.get_parity(),
+            repository,
+        };
 
-        let (tx, rx) =
-            create_threads(&mut monitor, pack_size, hasher, chunker, prepare_threads, compressor, encryptor, ecc);
+        let (tx, rx) = create_threads(&mut monitor, &options, components);
 
-        (Self { monitor }, tx, rx)
+        (Self { monitor, index }, tx, rx)
+    }
+
+    pub fn get_index(&self) -> &Arc<ChunkIndex> {
+        &self.index
     }
 
     pub fn join_threads(&self) {
@@ -47,16 +114,44 @@ impl Pipeline {
     }
 }
 
-fn create_threads(
-    monitor: &mut PipelineMonitor,
+/// What the steps of the pipeline use, resolved from the options.
+struct PipelineComponents {
     pack_size: u32,
     hasher: Arc<Hasher>,
     chunker: Arc<Chunker>,
+    index: Arc<ChunkIndex>,
+    delta_cache_size: usize,
+    chunk_threads: u8,
     prepare_threads: u8,
+    budget: Arc<MemoryBudget>,
     compressor: Arc<Compressor>,
     encryptor: Arc<Encryptor>,
     ecc: Arc<ECC>,
+    repository: Arc<Repository>,
+    parity: Option<ParityConfig>,
+}
+
+fn create_threads(
+    monitor: &mut PipelineMonitor,
+    options: &PipelineOptions,
+    components: PipelineComponents,
 ) -> (Sender<Arc<SnapshotBuilder>>, Receiver<Arc<SnapshotBuilder>>) {
+    let PipelineComponents {
+        pack_size,
+        hasher,
+        chunker,
+        index,
+        delta_cache_size,
+        chunk_threads,
+        prepare_threads,
+        budget,
+        compressor,
+        encryptor,
+        ecc,
+        repository,
+        parity,
+    } = components;
+
     let (walk_tx, walk_rx): (Sender<Arc<SnapshotBuilder>>, Receiver<Arc<SnapshotBuilder>>) = flume::unbounded();
     let (chunk_tx, chunk_rx) = flume::unbounded();
     let (pack_tx, pack_rx) = flume::bounded(0);
@@ -80,92 +175,269 @@ fn create_threads(
 
             let mut count = 0;
 
-            let result = path_walk(snapshot.get_root().to_path_buf(), |path, relative_path, metadata| {
-                match metadata {
-                    Err(err) => {
-                        let path = snapshot.add_path(path, relative_path, None);
-                        path.set_error(err.into());
-                    }
-                    Ok(metadata) => {
-                        let len = if metadata.is_file() { metadata.len() } else { 0 };
-
-                        let path = snapshot.add_path(path, relative_path, Some(metadata));
-
-                        if len == 0 {
-                            path.set_finished_adding_chunks(0);
-                        } else {
-                            ctx.send((snapshot.clone(), path));
+            let result = match snapshot.get_source() {
+                SnapshotSource::Folder(root, options) => {
+                    // The paths found for each file with several hard links
+                    let mut hard_links: HashMap<_, Arc<HardLinkGroup>> = HashMap::new();
+
+                    path_walk(root.path.clone(), options, |path, relative_path, info| {
+                        match info {
+                            Err(err) => {
+                                let path = snapshot.add_path(path, relative_path, None);
+                                path.set_error(err.into());
+                            }
+                            Ok((metadata, attributes)) => {
+                                let len = if metadata.is_file() { metadata.len() } else { 0 };
+
+                                let is_symlink = metadata.is_symlink();
+                                let group = hard_link_id(&metadata).map(|id| {
+                                    hard_links
+                                        .entry(id)
+                                        .or_insert_with(HardLinkGroup::new)
+                                        .clone()
+                                });
+                                let path = snapshot.add_path(path, relative_path, Some(metadata));
+
+                                if let Err(err) = attributes.map(|a| path.set_attributes(a)) {
+                                    path.set_error(err);
+                                } else if is_symlink {
+                                    match fs::read_link(path.get_path()) {
+                                        Err(err) => path.set_error(err.into()),
+                                        Ok(target) => {
+                                            path.set_link_target(target);
+                                            path.set_finished_adding_chunks(0);
+                                        }
+                                    }
+                                } else {
+                                    // Only one of the paths of a file with hard links is read, the others link to it
+                                    let read = group.as_ref().is_none_or(|g| g.add(&path));
+                                    if read && len == 0 {
+                                        path.set_finished_adding_chunks(0);
+                                        if let Some(group) = &group {
+                                            group.finish_reading(&path, true);
+                                        }
+                                    } else if read {
+                                        ctx.send((snapshot.clone(), path, group));
+                                    }
+                                }
+                            }
                         }
-                    }
+
+                        count += 1;
+                    })
                 }
+                SnapshotSource::Stream(name, _) => {
+                    let path = snapshot.add_virtual_file(name.clone());
+                    ctx.send((snapshot.clone(), path, None));
 
-                count += 1;
-            });
+                    count += 1;
+                    Ok(WalkSummary::default())
+                }
+            };
             match result {
                 Err(e) => snapshot.set_error(e),
-                Ok(_) => snapshot.set_finished_adding_paths(count),
+                Ok(summary) => {
+                    snapshot.add_skipped_paths(summary.skipped);
+                    snapshot.set_finished_adding_paths(count);
+                }
             };
 
             ctx.on_completed();
         });
 
-    monitor
-        .create_step("Chunk", &chunk_rx, &pack_tx)
-        .spawn_thread({
-            let chunker = chunker.clone();
+    {
+        let mut step = monitor.create_step("Chunk", &chunk_rx, &pack_tx);
+        let pool = ThreadPool::new(chunk_threads as usize);
 
-            move |mut ctx| loop {
-                let (snapshot, file) = recv!(ctx);
+        // Each file is chunked by a single thread, so its chunks are still created and sent in order. Chunks are
+        // also hashed here, so this is done in parallel too
+        for _ in 1..=chunk_threads {
+            step.spawn_thread({
+                let chunker = chunker.clone();
+                let hasher = hasher.clone();
+                let budget = budget.clone();
+                let pool = pool.clone();
+                let changed_files = options.changed_files;
+                #[cfg(test)]
+                let before_read = options.before_read.clone();
 
-                let mut chunks = 0;
+                move |mut ctx| loop {
+                    let (snapshot, file, group) = recv!(ctx);
+
+                    // If a path of a file with hard links can't be read, the next one is read instead
+                    let mut next = Some(file);
+                    while let Some(file) = next.take() {
+                        let len = file.get_metadata().map(|m| m.len()).unwrap_or(0);
+                        let threads = pool.take(if chunker.can_split_parallel(len) {
+                            chunk_threads as usize
+                        } else {
+                            1
+                        });
+
+                        // Zeros are not stored, whether they come from holes or not
+                        let mut add_hole = |size: u64| file.add_hole(size);
+
+                        // Files are read into buffers that don't change, so their chunks are only copied into the
+                        // pack. Chunks of a mmap would be copied before anything looks at them, so what is hashed is
+                        // what is stored, even if the file changes, and a file that shrinks can't make reads fault
+                        let mut add_chunk = |data: ChunkData| {
+                            let memory = budget.acquire(data.len() as u64);
+                            let data = data.into_owned();
+
+                            if data.iter().all(|b| *b == 0) {
+                                file.add_hole(data.len() as u64);
+                                return;
+                            }
+
+                            let chunk = file.add_chunk(data.len() as u32);
+                            let hash = hasher.hash(&data);
+                            chunk.set_hash(hash.clone());
+                            ctx.send((snapshot.clone(), file.clone(), chunk, data, hash, memory));
+                        };
+
+                        let result = match snapshot.get_source() {
+                            SnapshotSource::Stream(_, source) => source.open().and_then(|mut stream| {
+                                let result = chunker.split_reader(&mut stream, &mut add_chunk);
+                                let finished = stream.finish(result.is_ok());
+                                result.and(finished)
+                            }),
+                            // Very large files are also split using the idle chunk threads, and the holes of sparse
+                            // files are skipped. Then the file is checked for changes made while it was read
+                            SnapshotSource::Folder(..) => {
+                                let mut before = file.get_metadata().unwrap().clone();
+                                let mut retries = 0;
+
+                                loop {
+                                    #[cfg(test)]
+                                    if let Some(hook) = &before_read {
+                                        (hook.0)(&file, retries);
+                                    }
+
+                                    let result = chunker
+                                        .split_sparse(
+                                            file.get_path(),
+                                            &before,
+                                            threads.get_count(),
+                                            &mut add_chunk,
+                                            &mut add_hole,
+                                        )
+                                        .and_then(|_| Ok(fs::metadata(file.get_path())?));
+                                    let after = match result {
+                                        Err(e) => break Err(e),
+                                        Ok(after) => after,
+                                    };
+
+                                    if !has_changed(&before, &after) {
+                                        file.set_change_check(match retries {
+                                            0 => ChangeCheck::Unchanged,
+                                            n => ChangeCheck::UnchangedAfterRetries(n),
+                                        });
+                                        break Ok(());
+                                    }
+
+                                    match changed_files {
+                                        ChangedFilePolicy::Fail => break Err(Error::msg("changed while being read")),
+                                        ChangedFilePolicy::Retry(max) if retries < max => {
+                                            retries += 1;
+                                            file.reset_chunks();
+                                            if let Err(e) = PathAttributes::read(file.get_path(), &after)
+                                                .map(|a| file.set_attributes(a))
+                                            {
+                                                break Err(e);
+                                            }
+                                            before = after;
+                                        }
+                                        // The attributes from the walk are older than what was read
+                                        _ => {
+                                            file.set_change_check(ChangeCheck::Changed);
+                                            break PathAttributes::read(file.get_path(), &after)
+                                                .map(|a| file.set_attributes(a));
+                                        }
+                                    }
+                                }
+                            }
+                        };
+                        match result {
+                            // The virtual file is all there is in the snapshot
+                            Err(e) if file.is_virtual() => {
+                                snapshot.set_error(Error::msg(format!(
+                                    "error reading {}: {}",
+                                    file.get_relative_path(),
+                                    e
+                                )));
+                                file.set_error(e);
+                            }
+                            Err(e) => file.set_error(e),
+                            Ok(_) => file.set_finished_adding_chunks(file.get_chunk_count()),
+                        }
 
-                let result = chunker.split(file.get_path(), file.get_metadata().unwrap(), &mut |data| {
-                    let chunk = file.add_chunk(data.len() as u32);
-                    ctx.send((snapshot.clone(), file.clone(), chunk, data));
-                    chunks += 1;
-                });
-                match result {
-                    Err(e) => file.set_error(e),
-                    Ok(_) => file.set_finished_adding_chunks(chunks),
-                }
+                        if let Some(group) = &group {
+                            next = group.finish_reading(&file, !file.has_error());
+                        }
+                    }
 
-                ctx.on_completed();
-            }
-        });
+                    ctx.on_completed();
+                }
+            });
+        }
+    }
 
     monitor
         .create_step("Pack", &pack_rx, &pack_prepare_tx)
         .spawn_thread({
             let pack_capacity = pack_size + chunker.get_max_block_size() + encryptor.get_extra_space_needed();
-            let hasher = hasher.clone();
+            let index = index.clone();
 
             move |mut ctx| {
                 let mut pack = PackBuilder::new(pack_capacity);
+                let mut pack_memory = budget.empty();
+                let mut delta = (delta_cache_size > 0).then(|| DeltaCompressor::new(index.clone(), delta_cache_size));
 
                 loop {
-                    let (snapshot, file, chunk, data) = recv!(ctx);
+                    let (snapshot, file, chunk, data, hash, memory) = recv!(ctx);
+
+                    // Chunks from a read of a file that was then read again are not stored
+                    if chunk.is_discarded() {
+                        continue;
+                    }
 
-                    let hash = hasher.hash(&data);
-                    chunk.set_hash(hash);
+                    let data = match delta.as_mut() {
+                        None => {
+                            index.add(&hash, data.len() as u32, None);
+                            data
+                        }
+                        Some(delta) => {
+                            let (base, data) = delta.compress(&hash, data);
+                            if let Some(base) = base {
+                                chunk.set_delta_base(base);
+                            }
+                            data
+                        }
+                    };
 
                     pack.add_chunk(snapshot, file, chunk, data);
 
+                    // The chunk data is now in the pack buffer, possibly smaller after delta compression
+                    pack_memory.merge(memory);
+                    pack_memory.resize(pack.get_size_chunks() as u64);
+
                     if pack.get_size_chunks() > pack_size {
-                        ctx.send(pack);
+                        ctx.send((pack, pack_memory));
                         ctx.on_completed();
 
                         pack = PackBuilder::new(pack_capacity);
+                        pack_memory = budget.empty();
                     }
                 }
 
                 if pack.get_size_chunks() > 0 {
-                    ctx.send(pack);
+                    ctx.send((pack, pack_memory));
                     ctx.on_completed();
                 }
             }
         });
 
-    {
+    let prepare_stats = {
         let mut step = monitor.create_step("Prepare pack", &pack_prepare_rx, &store_pack_tx);
 
         for _ in 1..=prepare_threads {
@@ -176,38 +448,101 @@ fn create_threads(
                 let ecc = ecc.clone();
 
                 move |mut ctx| loop {
-                    let mut pack = recv!(ctx);
+                    let (mut pack, mut memory) = recv!(ctx);
 
                     let result =
                         prepare(&mut pack, hasher.as_ref(), compressor.as_ref(), encryptor.as_ref(), ecc.as_ref());
                     match result {
                         Err(e) => pack.set_error(e),
-                        Ok(_) => ctx.send(pack),
+                        Ok(_) => {
+                            memory.resize(pack.get_data().len() as u64);
+                            ctx.send((pack, memory));
+                        }
                     }
 
                     ctx.on_completed();
                 }
             });
         }
-    }
 
-    monitor
-        .create_step("Store pack", &store_pack_rx, &index_tx)
-        .spawn_thread(move |mut ctx| loop {
-            let mut pack = recv!(ctx);
+        step.get_stats()
+    };
+
+    {
+        let mut step = monitor.create_step("Store pack", &store_pack_rx, &index_tx);
+
+        let store_stats = step.get_stats();
+
+        step.spawn_thread(move |mut ctx| {
+            let mut balancer = ThroughputBalancer::new(prepare_stats.clone(), store_stats.clone());
+            let mut parity_group = parity.map(|p| ParityGroupBuilder::new(p).unwrap());
+
+            loop {
+                let (mut pack, memory) = recv!(ctx);
 
-            if let Some(e) = pack.take_error() {
-                for (_, file, chunk, _, _) in pack.chunks {
-                    file.set_error(Error::msg(format!("error creating pack with chunk {}: {}", chunk.get_index(), e)));
+                let result = match pack.take_error() {
+                    Some(e) => Err(e),
+                    None => store(&mut pack, repository.as_ref(), parity_group.as_mut()),
+                };
+
+                match result {
+                    Err(e) => {
+                        // A file read again doesn't fail because of the chunks of its previous read
+                        for (_, file, chunk, _, _) in pack.chunks {
+                            file.set_chunk_error(
+                                &chunk,
+                                Error::msg(format!("error creating pack with chunk {}: {}", chunk.get_index(), e)),
+                            );
+                        }
+                    }
+                    Ok(_) => {
+                        for (_, _, chunk, start, size) in &pack.chunks {
+                            let location = PackLocation::new(pack.get_hash().to_vec(), *start as u64, *size as u64);
+                            index.set_stored(&chunk.get_hash(), location.clone());
+                            chunk.set_stored(location);
+                        }
+                    }
+                }
+
+                drop(memory);
+                ctx.on_completed();
+
+                if let Some(bottleneck) = balancer.find_bottleneck() {
+                    if let Some(level) = compressor.adapt(bottleneck) {
+                        ctx.println(&format!(
+                            "{:?} is the bottleneck: compression level changed to {}",
+                            bottleneck, level
+                        ));
+                    }
                 }
             }
 
-            ctx.on_completed();
+            if let Some(group) = parity_group.as_mut().filter(|g| !g.is_empty()) {
+                if let Err(e) = group.write(repository.as_ref()) {
+                    ctx.println(&format!("error writing parity group: {}", e));
+                }
+            }
         });
+    }
 
     (walk_tx, index_rx)
 }
 
+/// Whether a file changed between the two reads of its metadata.
+#[cfg(unix)]
+fn has_changed(before: &fs::Metadata, after: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let version = |m: &fs::Metadata| (m.len(), m.mtime(), m.mtime_nsec(), m.ctime(), m.ctime_nsec(), m.ino());
+    version(before) != version(after)
+}
+
+#[cfg(not(unix))]
+fn has_changed(before: &fs::Metadata, after: &fs::Metadata) -> bool {
+    let version = |m: &fs::Metadata| (m.len(), m.modified().ok());
+    version(before) != version(after)
+}
+
 fn prepare(
     pack: &mut PackBuilder,
     hasher: &Hasher,
@@ -218,8 +553,12 @@ fn prepare(
     let hash = hasher.hash(pack.get_data());
     pack.set_hash(hash);
 
-    let compressed = compressor.compress(pack.take_data())?;
-    pack.set_compressed_data(compressed.0, compressed.1);
+    let (ct, level, compressed) = compressor.compress(pack.take_data())?;
+    let compressor_name = match ct {
+        CompressionType::NONE => "None",
+        _ => compressor.get_name(),
+    };
+    pack.set_compressed_data(ct, level, compressed);
 
     let encrypted = encryptor.encrypt(pack.take_data())?;
     pack.set_encrypted_data(encrypted.0, encrypted.1);
@@ -227,5 +566,318 @@ fn prepare(
     let after_ecc = ecc.write(pack.take_data())?;
     pack.set_ecc_data(after_ecc.0, after_ecc.1);
 
+    pack.set_header(&PackHeader {
+        hash: pack.get_hash().to_vec(),
+        hasher: hasher.get_name().to_string(),
+        chunks_size: pack.get_size_chunks() as u64,
+        compressor: compressor_name.to_string(),
+        compression_level: pack.get_compress_level(),
+        encryptor: encryptor.get_name().to_string(),
+        ecc: ecc.get_name().to_string(),
+        data_size: pack.get_data().len() as u64,
+    });
+
+    Ok(())
+}
+
+fn store(pack: &mut PackBuilder, repository: &Repository, parity_group: Option<&mut ParityGroupBuilder>) -> Result<()> {
+    let name = repository.write_pack(pack.get_hash(), pack.get_data())?;
+
+    if let Some(group) = parity_group {
+        group.add_pack(&name, pack.get_data())?;
+
+        if group.is_full() {
+            group.write(repository)?;
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::fmt;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::pack::index::ChunkIndex;
+    use crate::path_walk::WalkOptions;
+    use crate::pipeline::{has_changed, ChangedFilePolicy, Pipeline, PipelineOptions};
+    use crate::repository::Repository;
+    use crate::restore::Restorer;
+    use crate::snapshot::attributes::PathAttributes;
+    use crate::snapshot::builder::{ChangeCheck, PathBuilder, SnapshotBuilder};
+    use crate::storage::faulty::FaultProfile;
+    use crate::storage::Storage;
+    use crate::workspace::SharedItem;
+
+    /// Called with a file before it is read, and how many times it was read before.
+    #[derive(Clone)]
+    pub struct ReadHook(pub Arc<dyn Fn(&PathBuilder, u32) + Send + Sync>);
+
+    impl fmt::Debug for ReadHook {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("ReadHook")
+        }
+    }
+
+    /// Backs up `source` to a repository in `dir`.
+    fn backup(
+        source: &Path,
+        dir: &Path,
+        options: PipelineOptions,
+    ) -> (Arc<SnapshotBuilder>, Arc<Repository>, Arc<ChunkIndex>) {
+        let repository = Repository::new(Storage::build_local(&dir.join("repository")).unwrap());
+        let snapshot = SnapshotBuilder::new(SharedItem::build(source), WalkOptions::default());
+
+        let (pipeline, tx, rx) = Pipeline::new(options, repository.clone());
+        tx.send(snapshot.clone()).unwrap();
+        drop(tx);
+        for _ in rx {}
+        pipeline.join_threads();
+
+        assert!(snapshot.is_complete());
+
+        let index = pipeline.get_index().clone();
+        (snapshot, repository, index)
+    }
+
+    /// Backs up a file that is appended to before each of its first `changes` reads.
+    fn backup_changing_file(
+        changed_files: ChangedFilePolicy,
+        changes: u32,
+    ) -> (Arc<SnapshotBuilder>, Arc<PathBuilder>) {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("file"), "data").unwrap();
+
+        let options = PipelineOptions {
+            changed_files,
+            before_read: Some(ReadHook(Arc::new(move |file, retries| {
+                if retries < changes {
+                    let mut data = fs::read(file.get_path()).unwrap();
+                    data.extend_from_slice(b" more");
+                    fs::write(file.get_path(), data).unwrap();
+                }
+            }))),
+            ..Default::default()
+        };
+        let (snapshot, _, _) = backup(&source, temp.path(), options);
+
+        let file = snapshot
+            .get_paths()
+            .into_iter()
+            .find(|p| p.get_relative_path().as_str() == "file")
+            .unwrap();
+
+        // The attributes always match the data that was read. When it was last accessed changes by reading it
+        if !file.has_error() {
+            let attributes = file.get_attributes().unwrap();
+            let mut read = PathAttributes::read(file.get_path(), &fs::metadata(file.get_path()).unwrap()).unwrap();
+            read.accessed = attributes.accessed;
+            assert_eq!(read, attributes);
+        }
+
+        (snapshot, file)
+    }
+
+    #[test]
+    fn keeps_files_changed_while_read() {
+        let (snapshot, file) = backup_changing_file(ChangedFilePolicy::Keep, 1);
+
+        assert_eq!(Some(ChangeCheck::Changed), file.get_change_check());
+        assert_eq!((1, 1, 0), {
+            let summary = snapshot.get_summary();
+            (summary.files, summary.changed, summary.failed)
+        });
+    }
+
+    #[test]
+    fn reads_changed_files_again() {
+        let (snapshot, file) = backup_changing_file(ChangedFilePolicy::Retry(3), 2);
+
+        assert_eq!(Some(ChangeCheck::UnchangedAfterRetries(2)), file.get_change_check());
+        assert_eq!(0, snapshot.get_summary().changed);
+        let size: u64 = file.get_chunks().iter().map(|c| c.get_size() as u64).sum();
+        assert_eq!("data more more".len() as u64, size);
+
+        // Kept as changed once out of retries
+        let (snapshot, file) = backup_changing_file(ChangedFilePolicy::Retry(1), 2);
+
+        assert_eq!(Some(ChangeCheck::Changed), file.get_change_check());
+        assert_eq!(1, snapshot.get_summary().changed);
+    }
+
+    #[test]
+    fn fails_files_changed_while_read() {
+        let (snapshot, file) = backup_changing_file(ChangedFilePolicy::Fail, 1);
+
+        assert!(file.has_error());
+        assert_eq!((0, 0, 1), {
+            let summary = snapshot.get_summary();
+            (summary.files, summary.changed, summary.failed)
+        });
+    }
+
+    #[test]
+    fn ignores_store_errors_of_chunks_read_before_a_retry() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+        // More than a pack, so the chunks of the first read fill one before they are discarded
+        let mut data = vec![0u8; 25_000_000];
+        StdRng::seed_from_u64(9).fill(&mut data[..]);
+        fs::write(source.join("file"), &data).unwrap();
+
+        let options = PipelineOptions {
+            changed_files: ChangedFilePolicy::Retry(1),
+            before_read: Some(ReadHook(Arc::new(|file, retries| {
+                if retries == 0 {
+                    let mut data = fs::read(file.get_path()).unwrap();
+                    data.extend_from_slice(b" more");
+                    fs::write(file.get_path(), data).unwrap();
+                }
+            }))),
+            ..Default::default()
+        };
+        let mut profile = FaultProfile::new(1, "packs/");
+        profile.failed_writes = 1;
+        let storage = Storage::build_faulty(Storage::build_local(&temp.path().join("repository")).unwrap(), profile);
+        let snapshot = SnapshotBuilder::new(SharedItem::build(&source), WalkOptions::default());
+
+        let (pipeline, tx, rx) = Pipeline::new(options, Repository::new(storage));
+        tx.send(snapshot.clone()).unwrap();
+        drop(tx);
+        for _ in rx {}
+        pipeline.join_threads();
+
+        // The file only fails if the pack that couldn't be written has chunks of the second read
+        let file = snapshot
+            .get_paths()
+            .into_iter()
+            .find(|p| p.get_relative_path().as_str() == "file")
+            .unwrap();
+        let stored = file
+            .get_chunks()
+            .iter()
+            .all(|c| c.get_pack_location().is_some());
+        assert_eq!(stored, !file.has_error());
+    }
+
+    #[test]
+    fn stores_zeros_as_sparse_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+
+        // Zeros written as data, so the file has no holes
+        let mut data = vec![0u8; 12 << 20];
+        StdRng::seed_from_u64(4).fill(&mut data[..2 << 20]);
+        StdRng::seed_from_u64(5).fill(&mut data[10 << 20..]);
+        fs::write(source.join("zeros"), &data).unwrap();
+
+        let (snapshot, repository, index) = backup(&source, temp.path(), PipelineOptions::default());
+
+        let paths = snapshot.get_paths();
+        let file = paths
+            .iter()
+            .find(|p| p.get_relative_path().as_str() == "zeros")
+            .unwrap();
+        let sparse: u64 = file
+            .get_chunks()
+            .iter()
+            .filter(|c| c.is_sparse())
+            .map(|c| c.get_size() as u64)
+            .sum();
+        assert!(sparse >= 4 << 20, "only {} bytes are sparse", sparse);
+
+        let target = temp.path().join("target");
+        let report = Restorer::new(repository, index, "1234")
+            .restore_snapshot(&snapshot, &target)
+            .unwrap();
+        assert!(report.failed.is_empty());
+        assert!(data == fs::read(target.join("zeros")).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_hard_links_from_the_next_path_if_the_first_fails() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a"), "data").unwrap();
+        fs::hard_link(source.join("a"), source.join("b")).unwrap();
+        fs::hard_link(source.join("a"), source.join("c")).unwrap();
+
+        // The first path to be read is removed just before, so reading it fails
+        let removed = Arc::new(Mutex::new(None));
+        let options = PipelineOptions {
+            before_read: Some(ReadHook(Arc::new({
+                let removed = removed.clone();
+                move |file, _| {
+                    let mut removed = removed.lock().unwrap();
+                    if removed.is_none() {
+                        fs::remove_file(file.get_path()).unwrap();
+                        *removed = Some(file.get_relative_path().as_str().to_string());
+                    }
+                }
+            }))),
+            ..Default::default()
+        };
+        let (snapshot, repository, index) = backup(&source, temp.path(), options);
+
+        let removed = removed.lock().unwrap().clone().unwrap();
+        let paths = snapshot.get_paths();
+        let failed: Vec<_> = paths.iter().filter(|p| p.has_error()).collect();
+        assert_eq!(
+            vec![removed.as_str()],
+            failed
+                .iter()
+                .map(|p| p.get_relative_path().as_str())
+                .collect::<Vec<_>>()
+        );
+
+        let target = temp.path().join("target");
+        Restorer::new(repository, index, "1234")
+            .restore_snapshot(&snapshot, &target)
+            .unwrap();
+
+        let kept: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .filter(|n| *n != removed)
+            .collect();
+        for name in &kept {
+            assert_eq!(b"data".to_vec(), fs::read(target.join(name)).unwrap(), "{}", name);
+        }
+        let inode = |name: &str| fs::metadata(target.join(name)).unwrap().ino();
+        assert_eq!(inode(kept[0]), inode(kept[1]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_changed_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("file");
+        fs::write(&path, "data").unwrap();
+
+        let before = fs::metadata(&path).unwrap();
+        assert!(!has_changed(&before, &fs::metadata(&path).unwrap()));
+
+        fs::write(&path, "more data").unwrap();
+        assert!(has_changed(&before, &fs::metadata(&path).unwrap()));
+
+        // Same size, but written again
+        let before = fs::metadata(&path).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&path, "MORE DATA").unwrap();
+        assert!(has_changed(&before, &fs::metadata(&path).unwrap()));
+    }
+}
@@ -11,6 +11,7 @@ use indicatif::{ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
 use quanta::{Clock, Instant};
 
 use crate::metrics::ResponseTime;
+use crate::pipeline::budget::MemoryBudget;
 
 pub struct PipelineMonitor {
     clock: Clock,
@@ -49,6 +50,19 @@ impl PipelineMonitor {
         PipelineStep::new(self.clock.clone(), self, name, rx, tx, pg_bar)
     }
 
+    /// Creates a memory budget with its usage shown with the steps.
+    pub fn create_memory_budget(&self, limit: u64) -> Arc<MemoryBudget> {
+        let pg_bar = self.pg_multi.add(
+            ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr()).with_finish(ProgressFinish::Abandon),
+        );
+
+        let pg_style = format!("  {:18}  {{prefix:.dim}}  {{wide_msg}}", "Memory");
+        pg_bar.set_style(ProgressStyle::with_template(pg_style.as_str()).unwrap());
+        pg_bar.set_prefix("[  in use  ]");
+
+        MemoryBudget::new(limit, Some(pg_bar))
+    }
+
     fn add_thread(&self, handle: thread::JoinHandle<()>) {
         self.threads.lock().unwrap().push(handle);
     }
@@ -123,6 +137,53 @@ where
 
         self.monitor.add_thread(handle);
     }
+
+    pub fn get_stats(&self) -> PipelineStepStats {
+        PipelineStepStats {
+            recv_time: self.progress.recv_time.clone(),
+            process_time: self.progress.process_time.clone(),
+            send_time: self.progress.send_time.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PipelineStepStats {
+    recv_time: ResponseTime,
+    process_time: ResponseTime,
+    send_time: ResponseTime,
+}
+
+impl PipelineStepStats {
+    pub fn get_times(&self) -> PipelineStepTimes {
+        PipelineStepTimes {
+            count: self.recv_time.get_count(),
+            recv: self.recv_time.get_sum(),
+            run: self.process_time.get_sum(),
+            send: self.send_time.get_sum(),
+        }
+    }
+}
+
+/// Accumulated time spent by all threads of a step, split into waiting to receive (RX), processing (Run) and waiting
+/// to send (TX).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStepTimes {
+    pub count: u64,
+    pub recv: Duration,
+    pub run: Duration,
+    pub send: Duration,
+}
+
+impl PipelineStepTimes {
+    pub fn since(&self, previous: &PipelineStepTimes) -> PipelineStepTimes {
+        PipelineStepTimes {
+            count: self.count.saturating_sub(previous.count),
+            recv: self.recv.saturating_sub(previous.recv),
+            run: self.run.saturating_sub(previous.run),
+            send: self.send.saturating_sub(previous.send),
+        }
+    }
 }
 
 pub struct PipelineThreadContext<I, O>
@@ -0,0 +1,84 @@
+use std::cmp::min;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Limits the threads chunking files. Each file takes one, and very large files also take the idle ones to be split
+/// in parallel, so the threads of all the files together never go over the limit.
+pub struct ThreadPool {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Arc<ThreadPool> {
+        Arc::new(ThreadPool {
+            available: Mutex::new(threads),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Waits for at least one thread to be available, and takes up to `max` of them.
+    pub fn take(self: &Arc<Self>, max: usize) -> PooledThreads {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+
+        let count = min(*available, max.max(1));
+        *available -= count;
+
+        PooledThreads {
+            pool: self.clone(),
+            count,
+        }
+    }
+}
+
+/// Threads taken from a `ThreadPool`, given back when dropped.
+pub struct PooledThreads {
+    pool: Arc<ThreadPool>,
+    count: usize,
+}
+
+impl PooledThreads {
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Drop for PooledThreads {
+    fn drop(&mut self) {
+        *self.pool.available.lock().unwrap() += self.count;
+        self.pool.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::pipeline::threads::ThreadPool;
+
+    #[test]
+    fn take_shares_the_threads() {
+        let pool = ThreadPool::new(4);
+
+        let first = pool.take(1);
+        let large = pool.take(4);
+        assert_eq!((1, 3), (first.get_count(), large.get_count()));
+
+        let waiting = thread::spawn({
+            let pool = pool.clone();
+            move || pool.take(4).get_count()
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        assert_eq!(1, waiting.join().unwrap());
+
+        drop(large);
+        assert_eq!(4, pool.take(4).get_count());
+    }
+}
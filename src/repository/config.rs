@@ -0,0 +1,159 @@
+use std::fmt::Write;
+
+use anyhow::{Error, Result};
+use uuid::Uuid;
+
+use super::*;
+
+pub const CONFIG_NAME: &str = "config";
+const CONFIG_HEADER: &str = "mfsb repository v1";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepositoryConfig {
+    pub id: Uuid,
+    /// Store PAR2 recovery files next to each pack.
+    pub par2: Option<Par2Config>,
+    /// Store parity packs for groups of new packs.
+    pub parity: Option<ParityConfig>,
+}
+
+impl RepositoryConfig {
+    fn to_text(&self) -> String {
+        let mut result = String::new();
+        writeln!(result, "{}", CONFIG_HEADER).unwrap();
+        writeln!(result, "id {}", self.id).unwrap();
+        if let Some(par2) = &self.par2 {
+            writeln!(result, "par2 {} {}", par2.slice_size, par2.redundancy_percent).unwrap();
+        }
+        if let Some(parity) = &self.parity {
+            writeln!(result, "parity {} {}", parity.data_packs, parity.parity_packs).unwrap();
+        }
+        result
+    }
+
+    fn parse(text: &str) -> Result<RepositoryConfig> {
+        let mut lines = text.lines();
+        anyhow::ensure!(lines.next() == Some(CONFIG_HEADER), "invalid repository config header");
+
+        let mut id = None;
+        let mut par2 = None;
+        let mut parity = None;
+
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["id", v] => id = Some(Uuid::parse_str(v)?),
+                ["par2", slice_size, redundancy_percent] => {
+                    par2 = Some(Par2Config {
+                        slice_size: slice_size.parse()?,
+                        redundancy_percent: redundancy_percent.parse()?,
+                    })
+                }
+                ["parity", data_packs, parity_packs] => {
+                    parity = Some(ParityConfig {
+                        data_packs: data_packs.parse()?,
+                        parity_packs: parity_packs.parse()?,
+                    })
+                }
+                [""] => {}
+                _ => return Err(Error::msg(format!("invalid repository config line: '{}'", line))),
+            }
+        }
+
+        Ok(RepositoryConfig {
+            id: id.ok_or_else(|| Error::msg("repository config without id"))?,
+            par2,
+            parity,
+        })
+    }
+}
+
+impl Repository {
+    /// Opens the repository in the storage, creating it if it is new, with the options in its config.
+    pub fn open(storage: Arc<Storage>) -> Result<Arc<Repository>> {
+        let config = Self::new(storage.clone()).init()?;
+
+        Ok(Self::build(
+            storage,
+            RepositoryOptions {
+                par2: config.par2,
+                parity: config.parity,
+            },
+        ))
+    }
+
+    /// Reads the repository config, creating it if the repository is new.
+    pub fn init(&self) -> Result<RepositoryConfig> {
+        if !self.list_metadata(CONFIG_NAME)?.is_empty() {
+            return RepositoryConfig::parse(&String::from_utf8(self.read_metadata(CONFIG_NAME)?)?);
+        }
+
+        let config = RepositoryConfig {
+            id: Uuid::new_v4(),
+            par2: None,
+            parity: None,
+        };
+        self.write_config(&config)?;
+
+        Ok(config)
+    }
+
+    /// Replaces the repository config. The options are only used when the repository is opened again.
+    pub fn write_config(&self, config: &RepositoryConfig) -> Result<()> {
+        self.write_metadata(CONFIG_NAME, config.to_text().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::par2::Par2Config;
+    use crate::repository::parity::ParityConfig;
+    use crate::repository::{Par2Status, Repository};
+    use crate::storage::Storage;
+
+    #[test]
+    fn opens_with_par2_from_the_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let storage = Storage::build_local(&dir).unwrap();
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 253) as u8).collect();
+
+        let repository = Repository::open(storage.clone()).unwrap();
+        let name = repository.write_pack(&[1; 4], &data).unwrap();
+        assert_eq!(Par2Status::NoRecoveryFiles, repository.repair_pack_with_par2(&name).unwrap());
+
+        let mut config = repository.init().unwrap();
+        config.par2 = Some(Par2Config::default());
+        repository.write_config(&config).unwrap();
+
+        let repository = Repository::open(storage.clone()).unwrap();
+        assert_eq!(config, repository.init().unwrap());
+        let name = repository.write_pack(&[2; 4], &data).unwrap();
+
+        let mut damaged = data.clone();
+        damaged[1000..2000].fill(0);
+        storage.write(&name, &damaged).unwrap();
+        assert_eq!(Par2Status::Repaired, repository.repair_pack_with_par2(&name).unwrap());
+        assert_eq!(data, storage.read(&name).unwrap());
+    }
+
+    #[test]
+    fn keeps_parity_in_the_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let storage = Storage::build_local(&temp.path()).unwrap();
+
+        let repository = Repository::open(storage.clone()).unwrap();
+        assert_eq!(None, repository.get_parity());
+
+        let mut config = repository.init().unwrap();
+        config.parity = Some(ParityConfig {
+            data_packs: 6,
+            parity_packs: 2,
+        });
+        repository.write_config(&config).unwrap();
+
+        let repository = Repository::open(storage).unwrap();
+        assert_eq!(config, repository.init().unwrap());
+        assert_eq!(config.parity, repository.get_parity());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Error, Result};
+
+use super::*;
+
+const MAGIC: &[u8; 4] = b"mfMD";
+const COPY_SUFFIXES: [&str; 3] = ["", ".copy1", ".copy2"];
+
+/// Metadata files (the config and the parity group manifests) are small but everything else depends on them, so each
+/// one is stored in several copies, each with a CRC32. Readers use the first intact copy.
+impl Repository {
+    pub fn write_metadata(&self, name: &str, data: &[u8]) -> Result<()> {
+        let mut framed = Vec::with_capacity(data.len() + 12);
+        framed.extend_from_slice(MAGIC);
+        framed.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        framed.extend_from_slice(data);
+        let crc = crc32fast::hash(&framed);
+        framed.extend_from_slice(&crc.to_le_bytes());
+
+        for suffix in COPY_SUFFIXES {
+            self.storage
+                .write(&format!("{}{}", name, suffix), &framed)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_metadata(&self, name: &str) -> Result<Vec<u8>> {
+        self.read_metadata_copies(name)
+            .with_context(|| format!("error reading {}", name))
+    }
+
+    /// Lists the metadata files with the prefix (without the names of the extra copies).
+    pub fn list_metadata(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut result: Vec<String> = self
+            .storage
+            .list(prefix)?
+            .into_iter()
+            .map(|name| match COPY_SUFFIXES[1..].iter().find(|s| name.ends_with(*s)) {
+                Some(suffix) => name[..name.len() - suffix.len()].to_string(),
+                None => name,
+            })
+            .collect();
+        result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    /// Rewrites the damaged or missing copies of a metadata file. Returns how many were rewritten.
+    pub fn repair_metadata(&self, name: &str) -> Result<usize> {
+        let copies: Vec<Result<Vec<u8>>> = COPY_SUFFIXES
+            .iter()
+            .map(|s| self.read_metadata_copy(name, s))
+            .collect();
+
+        let damaged = copies.iter().filter(|c| c.is_err()).count();
+        if damaged > 0 {
+            let data = self.read_metadata(name)?;
+            self.write_metadata(name, &data)?;
+        }
+
+        Ok(damaged)
+    }
+
+    fn read_metadata_copies(&self, name: &str) -> Result<Vec<u8>> {
+        let mut last_error = None;
+
+        for suffix in COPY_SUFFIXES {
+            match self.read_metadata_copy(name, suffix) {
+                Ok(data) => return Ok(data),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap().context("all copies are damaged"))
+    }
+
+    fn read_metadata_copy(&self, name: &str, suffix: &str) -> Result<Vec<u8>> {
+        let framed = self.storage.read(&format!("{}{}", name, suffix))?;
+        unframe(&framed)
+    }
+}
+
+fn unframe(framed: &[u8]) -> Result<Vec<u8>> {
+    anyhow::ensure!(framed.len() >= 12 && &framed[..4] == MAGIC, "invalid metadata file");
+
+    let size = u32::from_le_bytes(framed[4..8].try_into().unwrap()) as usize;
+    anyhow::ensure!(framed.len() == size + 12, "metadata file has the wrong size");
+
+    let crc = u32::from_le_bytes(framed[8 + size..].try_into().unwrap());
+    if crc32fast::hash(&framed[..8 + size]) != crc {
+        return Err(Error::msg("metadata file checksum does not match"));
+    }
+
+    Ok(framed[8..8 + size].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::Repository;
+    use crate::storage::Storage;
+
+    #[test]
+    fn falls_back_to_other_copies() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let repository = Repository::new(Storage::build_local(&dir).unwrap());
+        let storage = repository.get_storage();
+
+        repository.write_metadata("keys/a", b"key data").unwrap();
+        assert_eq!(vec!["keys/a"], repository.list_metadata("keys/").unwrap());
+
+        let mut damaged = storage.read("keys/a").unwrap();
+        damaged[9] ^= 0x01;
+        storage.write("keys/a", &damaged).unwrap();
+        storage.delete("keys/a.copy2").unwrap();
+
+        assert_eq!(b"key data".to_vec(), repository.read_metadata("keys/a").unwrap());
+        assert_eq!(2, repository.repair_metadata("keys/a").unwrap());
+        assert_eq!(0, repository.repair_metadata("keys/a").unwrap());
+
+        for name in ["keys/a", "keys/a.copy1", "keys/a.copy2"] {
+            storage.write(name, b"garbage").unwrap();
+        }
+        assert!(repository.read_metadata("keys/a").is_err());
+    }
+}
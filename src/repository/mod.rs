@@ -0,0 +1,160 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::par2;
+use crate::par2::{Par2Config, RecoverySet, Verification};
+use crate::repository::parity::ParityConfig;
+use crate::storage::Storage;
+
+pub use config::{RepositoryConfig, CONFIG_NAME};
+
+mod config;
+mod metadata;
+pub mod parity;
+pub mod scrub;
+
+const PACKS_PREFIX: &str = "packs/";
+const PAR2_SUFFIX: &str = ".par2";
+
+#[derive(Clone, Debug, Default)]
+pub struct RepositoryOptions {
+    /// Store PAR2 recovery files next to each pack. With local storage they can be used by the standard PAR2 tools.
+    pub par2: Option<Par2Config>,
+    /// Store parity packs for groups of new packs, so lost or damaged packs can be rebuilt from the rest of the group.
+    pub parity: Option<ParityConfig>,
+}
+
+pub struct Repository {
+    storage: Arc<Storage>,
+    options: RepositoryOptions,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Par2Status {
+    NoRecoveryFiles,
+    Intact,
+    Repaired,
+}
+
+impl Repository {
+    pub fn new(storage: Arc<Storage>) -> Arc<Repository> {
+        Self::build(storage, RepositoryOptions::default())
+    }
+
+    pub fn build(storage: Arc<Storage>, options: RepositoryOptions) -> Arc<Repository> {
+        Arc::new(Repository { storage, options })
+    }
+
+    pub fn get_storage(&self) -> &Arc<Storage> {
+        &self.storage
+    }
+
+    pub fn get_parity(&self) -> Option<ParityConfig> {
+        self.options.parity
+    }
+
+    pub fn get_pack_name(hash: &[u8]) -> String {
+        let hex = to_hex(hash);
+        format!("{}{}/{}", PACKS_PREFIX, &hex[..2], hex)
+    }
+
+    /// Stores the final (compressed, encrypted, with ECC) data of a pack. Returns the name of the stored object.
+    pub fn write_pack(&self, hash: &[u8], data: &[u8]) -> Result<String> {
+        let name = Self::get_pack_name(hash);
+        self.storage.write(&name, data)?;
+
+        if let Some(config) = &self.options.par2 {
+            let file_name = name.rsplit('/').next().unwrap();
+            for (suffix, recovery) in par2::create(file_name, data, config)? {
+                self.storage
+                    .write(&format!("{}{}", name, suffix), &recovery)?;
+            }
+        }
+
+        Ok(name)
+    }
+
+    pub fn read_pack(&self, hash: &[u8]) -> Result<Vec<u8>> {
+        self.storage.read(&Self::get_pack_name(hash))
+    }
+
+    pub fn list_packs(&self) -> Result<Vec<String>> {
+        let mut result = self.storage.list(PACKS_PREFIX)?;
+        result.retain(|n| !n.ends_with(PAR2_SUFFIX));
+        Ok(result)
+    }
+
+    /// Checks a pack against its PAR2 recovery files, rewriting it if it was damaged and could be repaired.
+    pub fn repair_pack_with_par2(&self, name: &str) -> Result<Par2Status> {
+        let mut recovery_files = Vec::new();
+        for file in self.storage.list(name)? {
+            if file.ends_with(PAR2_SUFFIX) {
+                recovery_files.push(self.storage.read(&file)?);
+            }
+        }
+        if recovery_files.is_empty() {
+            return Ok(Par2Status::NoRecoveryFiles);
+        }
+
+        let set = RecoverySet::parse(&recovery_files)?;
+        let data = self.storage.read(name).unwrap_or_default();
+
+        match set.verify(&data) {
+            Verification::Intact => Ok(Par2Status::Intact),
+            Verification::Damaged(..) => {
+                let repaired = set.repair(&data)?;
+                self.storage.write(name, &repaired)?;
+                Ok(Par2Status::Repaired)
+            }
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len() * 2);
+    for b in data {
+        write!(result, "{:02x}", b).unwrap();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::par2::Par2Config;
+    use crate::repository::{Par2Status, Repository, RepositoryOptions};
+    use crate::storage::Storage;
+
+    #[test]
+    fn repairs_packs_with_par2() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let options = RepositoryOptions {
+            par2: Some(Par2Config {
+                slice_size: 4096,
+                redundancy_percent: 10,
+            }),
+            ..Default::default()
+        };
+        let repository = Repository::build(Storage::build_local(&dir).unwrap(), options);
+        let storage = repository.get_storage();
+
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 253) as u8).collect();
+        let name = repository.write_pack(&[1, 2, 3, 4], &data).unwrap();
+
+        assert_eq!(vec![name.clone()], repository.list_packs().unwrap());
+        assert_eq!(Par2Status::Intact, repository.repair_pack_with_par2(&name).unwrap());
+
+        let mut damaged = data.clone();
+        damaged[4096..9000].fill(0);
+        damaged.truncate(192_512);
+        storage.write(&name, &damaged).unwrap();
+
+        assert_eq!(Par2Status::Repaired, repository.repair_pack_with_par2(&name).unwrap());
+        assert_eq!(data, storage.read(&name).unwrap());
+
+        storage.delete(&name).unwrap();
+        assert!(repository.repair_pack_with_par2(&name).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::cmp::max;
+
+use anyhow::{Context, Error, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use uuid::Uuid;
+
+use super::*;
+
+const MANIFEST_PREFIX: &str = "index/parity/";
+const MANIFEST_HEADER: &str = "mfsb parity group v1";
+
+/// Parity packs are computed over groups of `data_packs` packs with Reed-Solomon, so any `parity_packs` files of a
+/// group can be lost and rebuilt from the others. One parity pack works like RAID-5, two like RAID-6.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParityConfig {
+    pub data_packs: usize,
+    pub parity_packs: usize,
+}
+
+/// A stored object that is part of a parity group, with what is needed to check it is intact.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParityMember {
+    pub name: String,
+    pub size: u64,
+    pub crc: u32,
+}
+
+impl ParityMember {
+    fn new(name: String, data: &[u8]) -> Self {
+        Self {
+            name,
+            size: data.len() as u64,
+            crc: crc32fast::hash(data),
+        }
+    }
+
+    fn is_intact(&self, data: &[u8]) -> bool {
+        data.len() as u64 == self.size && crc32fast::hash(data) == self.crc
+    }
+}
+
+/// A group of packs and the parity computed over them, as recorded in the repository index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParityGroup {
+    pub id: String,
+    pub config: ParityConfig,
+    pub shard_size: u64,
+    pub packs: Vec<ParityMember>,
+    pub parity: Vec<ParityMember>,
+}
+
+impl ParityGroup {
+    fn get_manifest_name(id: &str) -> String {
+        format!("{}{}", MANIFEST_PREFIX, id)
+    }
+
+    fn get_parity_name(id: &str, index: usize) -> String {
+        format!("parity/{}/{}", id, index)
+    }
+
+    fn to_manifest(&self) -> String {
+        let mut result = String::new();
+        writeln!(result, "{}", MANIFEST_HEADER).unwrap();
+        writeln!(result, "data_packs {}", self.config.data_packs).unwrap();
+        writeln!(result, "parity_packs {}", self.config.parity_packs).unwrap();
+        writeln!(result, "shard_size {}", self.shard_size).unwrap();
+        for m in &self.packs {
+            writeln!(result, "pack {} {} {:08x}", m.name, m.size, m.crc).unwrap();
+        }
+        for m in &self.parity {
+            writeln!(result, "parity {} {} {:08x}", m.name, m.size, m.crc).unwrap();
+        }
+        result
+    }
+
+    fn parse_manifest(id: &str, text: &str) -> Result<ParityGroup> {
+        let mut lines = text.lines();
+        anyhow::ensure!(lines.next() == Some(MANIFEST_HEADER), "invalid parity manifest header");
+
+        let mut result = ParityGroup {
+            id: id.to_string(),
+            config: ParityConfig {
+                data_packs: 0,
+                parity_packs: 0,
+            },
+            shard_size: 0,
+            packs: Vec::new(),
+            parity: Vec::new(),
+        };
+
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["data_packs", v] => result.config.data_packs = v.parse()?,
+                ["parity_packs", v] => result.config.parity_packs = v.parse()?,
+                ["shard_size", v] => result.shard_size = v.parse()?,
+                [kind @ ("pack" | "parity"), name, size, crc] => {
+                    let member = ParityMember {
+                        name: name.to_string(),
+                        size: size.parse()?,
+                        crc: u32::from_str_radix(crc, 16)?,
+                    };
+                    if *kind == "pack" {
+                        result.packs.push(member);
+                    } else {
+                        result.parity.push(member);
+                    }
+                }
+                [""] => {}
+                _ => return Err(Error::msg(format!("invalid parity manifest line: '{}'", line))),
+            }
+        }
+
+        anyhow::ensure!(
+            result.packs.len() <= result.config.data_packs && result.parity.len() == result.config.parity_packs,
+            "invalid parity manifest: wrong number of members"
+        );
+
+        Ok(result)
+    }
+}
+
+/// Computes the parity of a group incrementally, as packs are stored, so only the parity is kept in memory.
+pub struct ParityGroupBuilder {
+    config: ParityConfig,
+    rs: ReedSolomon,
+    packs: Vec<ParityMember>,
+    parity: Vec<Vec<u8>>,
+}
+
+impl ParityGroupBuilder {
+    pub fn new(config: ParityConfig) -> Result<Self> {
+        let rs = ReedSolomon::new(config.data_packs, config.parity_packs)
+            .map_err(|e| Error::msg(format!("invalid parity config {:?}: {:?}", config, e)))?;
+
+        Ok(Self {
+            config,
+            rs,
+            packs: Vec::new(),
+            parity: vec![Vec::new(); config.parity_packs],
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packs.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.packs.len() >= self.config.data_packs
+    }
+
+    pub fn add_pack(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        anyhow::ensure!(!self.is_full(), "parity group is full");
+
+        // Missing bytes at the end of the shorter packs are treated as zeros, which don't change the parity
+        let size = max(data.len(), self.parity[0].len());
+        for p in self.parity.iter_mut() {
+            p.resize(size, 0);
+        }
+
+        let mut parity: Vec<&mut [u8]> = self
+            .parity
+            .iter_mut()
+            .map(|p| &mut p[..data.len()])
+            .collect();
+        self.rs
+            .encode_single_sep(self.packs.len(), data, &mut parity)
+            .map_err(|e| Error::msg(format!("error computing parity: {:?}", e)))?;
+
+        self.packs.push(ParityMember::new(name.to_string(), data));
+
+        Ok(())
+    }
+
+    /// Stores the parity packs and records the group in the repository index. A new group is started even if this
+    /// fails, so the next packs can still be protected: the packs of the failed group are left without parity.
+    pub fn write(&mut self, repository: &Repository) -> Result<ParityGroup> {
+        let packs = std::mem::take(&mut self.packs);
+        let parity_data = std::mem::replace(&mut self.parity, vec![Vec::new(); self.config.parity_packs]);
+
+        let id = Uuid::new_v4().to_string();
+        let storage = repository.get_storage();
+
+        let mut parity = Vec::new();
+        for (i, data) in parity_data.iter().enumerate() {
+            let name = ParityGroup::get_parity_name(&id, i);
+            storage.write(&name, data)?;
+            parity.push(ParityMember::new(name, data));
+        }
+
+        let group = ParityGroup {
+            id,
+            config: self.config,
+            shard_size: parity_data[0].len() as u64,
+            packs,
+            parity,
+        };
+
+        repository.write_metadata(&ParityGroup::get_manifest_name(&group.id), group.to_manifest().as_bytes())?;
+
+        Ok(group)
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct RepairReport {
+    pub groups: usize,
+    pub checked: usize,
+    pub repaired: Vec<String>,
+    pub failed: Vec<(String, Error)>,
+}
+
+impl Repository {
+    pub fn list_parity_groups(&self) -> Result<Vec<ParityGroup>> {
+        let mut result = Vec::new();
+
+        for name in self.list_metadata(MANIFEST_PREFIX)? {
+            let id = &name[MANIFEST_PREFIX.len()..];
+            let text = String::from_utf8(self.read_metadata(&name)?)?;
+            result.push(ParityGroup::parse_manifest(id, &text).with_context(|| format!("error reading {}", name))?);
+        }
+
+        Ok(result)
+    }
+
+    /// Rewrites the damaged copies of metadata files, then checks all packs (and parity packs) in parity groups and
+    /// rebuilds the ones that are missing or damaged.
+    pub fn repair(&self) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+
+        let mut metadata = self.list_metadata(CONFIG_NAME)?;
+        metadata.extend(self.list_metadata(MANIFEST_PREFIX)?);
+        for name in metadata {
+            report.checked += 1;
+
+            match self.repair_metadata(&name) {
+                Ok(0) => {}
+                Ok(_) => report.repaired.push(name),
+                Err(e) => report.failed.push((name, e)),
+            }
+        }
+
+        for group in self.list_parity_groups()? {
+            report.groups += 1;
+            report.checked += group.packs.len() + group.parity.len();
+
+            match self.repair_group(&group) {
+                Ok(repaired) => report.repaired.extend(repaired),
+                Err(e) => report.failed.push((group.id.clone(), e)),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Rebuilds the missing or damaged members of a parity group. Returns the names of the rebuilt objects.
+    pub fn repair_group(&self, group: &ParityGroup) -> Result<Vec<String>> {
+        let config = group.config;
+        let shard_size = group.shard_size as usize;
+
+        let read = |m: &ParityMember| -> Option<Vec<u8>> {
+            let mut data = self.storage.read(&m.name).ok()?;
+            if !m.is_intact(&data) {
+                return None;
+            }
+            data.resize(shard_size, 0);
+            Some(data)
+        };
+
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(config.data_packs + config.parity_packs);
+        shards.extend(group.packs.iter().map(read));
+        shards.resize(config.data_packs, Some(vec![0; shard_size]));
+        shards.extend(group.parity.iter().map(read));
+
+        let damaged: Vec<usize> = (0..shards.len()).filter(|i| shards[*i].is_none()).collect();
+        if damaged.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rs = ReedSolomon::new(config.data_packs, config.parity_packs)
+            .map_err(|e| Error::msg(format!("invalid parity config {:?}: {:?}", config, e)))?;
+        rs.reconstruct(&mut shards).map_err(|_| {
+            Error::msg(format!("too many damaged packs in parity group {}: {}", group.id, damaged.len()))
+        })?;
+
+        let mut result = Vec::new();
+        for i in damaged {
+            let member = if i < config.data_packs {
+                &group.packs[i]
+            } else {
+                &group.parity[i - config.data_packs]
+            };
+
+            let data = &shards[i].as_ref().unwrap()[..member.size as usize];
+            anyhow::ensure!(member.is_intact(data), "rebuilt {} does not match its checksum", member.name);
+
+            self.storage.write(&member.name, data)?;
+            result.push(member.name.clone());
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::parity::{ParityConfig, ParityGroupBuilder};
+    use crate::repository::Repository;
+    use crate::storage::faulty::FaultProfile;
+    use crate::storage::Storage;
+
+    #[test]
+    fn rebuilds_lost_packs() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let repository = Repository::new(Storage::build_local(&dir).unwrap());
+        let storage = repository.get_storage();
+
+        let config = ParityConfig {
+            data_packs: 4,
+            parity_packs: 2,
+        };
+        let mut builder = ParityGroupBuilder::new(config).unwrap();
+
+        let packs: Vec<Vec<u8>> = (0..3u8)
+            .map(|i| vec![i + 1; 1000 + i as usize * 300])
+            .collect();
+        let mut names = Vec::new();
+        for (i, data) in packs.iter().enumerate() {
+            let name = repository.write_pack(&[i as u8; 4], data).unwrap();
+            builder.add_pack(&name, data).unwrap();
+            names.push(name);
+        }
+        builder.write(&repository).unwrap();
+
+        storage.delete(&names[0]).unwrap();
+        storage.write(&names[2], &packs[2][..100]).unwrap();
+
+        let report = repository.repair().unwrap();
+        assert_eq!(1, report.groups);
+        assert_eq!(2, report.repaired.len());
+        assert!(report.failed.is_empty());
+
+        for (name, data) in names.iter().zip(packs.iter()) {
+            assert_eq!(data, &storage.read(name).unwrap());
+        }
+    }
+
+    #[test]
+    fn starts_a_new_group_after_a_failed_write() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut profile = FaultProfile::new(1, "parity/");
+        profile.failed_writes = 1;
+        let repository = Repository::new(Storage::build_faulty(Storage::build_local(&dir).unwrap(), profile));
+
+        let config = ParityConfig {
+            data_packs: 2,
+            parity_packs: 1,
+        };
+        let mut builder = ParityGroupBuilder::new(config).unwrap();
+
+        for i in 0..6u8 {
+            let data = vec![i + 1; 1000];
+            let name = repository.write_pack(&[i; 4], &data).unwrap();
+            builder.add_pack(&name, &data).unwrap();
+
+            if builder.is_full() {
+                assert_eq!(i == 1, builder.write(&repository).is_err());
+            }
+        }
+
+        assert!(builder.is_empty());
+        assert_eq!(2, repository.list_parity_groups().unwrap().len());
+    }
+}
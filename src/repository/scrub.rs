@@ -0,0 +1,208 @@
+use std::fmt;
+
+use anyhow::{Error, Result};
+
+use crate::ecc::{ECCReport, ECC};
+use crate::pack::header::PackHeader;
+
+use super::*;
+
+/// The state of a pack after it was scrubbed.
+#[derive(Debug)]
+pub struct PackHealth {
+    pub name: String,
+    pub report: ECCReport,
+    /// The pack had corrected errors and was rewritten with the corrected data.
+    pub rewritten: bool,
+    pub error: Option<Error>,
+}
+
+impl PackHealth {
+    pub fn is_damaged(&self) -> bool {
+        self.report.uncorrectable > 0 || self.error.is_some()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    pub packs: Vec<PackHealth>,
+}
+
+impl ScrubReport {
+    pub fn get_total(&self) -> ECCReport {
+        let mut result = ECCReport::default();
+        for pack in &self.packs {
+            result.add(&pack.report);
+        }
+        result
+    }
+
+    pub fn get_rewritten(&self) -> usize {
+        self.packs.iter().filter(|p| p.rewritten).count()
+    }
+
+    pub fn get_damaged(&self) -> usize {
+        self.packs.iter().filter(|p| p.is_damaged()).count()
+    }
+}
+
+impl fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for pack in &self.packs {
+            if let Some(err) = &pack.error {
+                writeln!(f, "{}: {}", pack.name, err)?;
+            } else if pack.report.corrected > 0 || pack.report.uncorrectable > 0 {
+                writeln!(
+                    f,
+                    "{}: {} corrected, {} uncorrectable of {} blocks{}",
+                    pack.name,
+                    pack.report.corrected,
+                    pack.report.uncorrectable,
+                    pack.report.blocks,
+                    if pack.rewritten { " (rewritten)" } else { "" }
+                )?;
+            }
+        }
+
+        let total = self.get_total();
+        write!(
+            f,
+            "Scrubbed {} packs ({} blocks): {} blocks corrected, {} uncorrectable, {} packs rewritten, {} damaged",
+            self.packs.len(),
+            total.blocks,
+            total.corrected,
+            total.uncorrectable,
+            self.get_rewritten(),
+            self.get_damaged()
+        )
+    }
+}
+
+impl Repository {
+    /// Reads all packs through their ECC, rewriting the ones that had only correctable errors.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+
+        for name in self.list_packs()? {
+            report.packs.push(self.scrub_pack(&name));
+        }
+
+        Ok(report)
+    }
+
+    pub fn scrub_pack(&self, name: &str) -> PackHealth {
+        let mut health = PackHealth {
+            name: name.to_string(),
+            report: ECCReport::default(),
+            rewritten: false,
+            error: None,
+        };
+
+        if let Err(e) = self.scrub_pack_data(name, &mut health) {
+            health.error = Some(e);
+        }
+
+        health
+    }
+
+    fn scrub_pack_data(&self, name: &str, health: &mut PackHealth) -> Result<()> {
+        let stored = self.storage.read(name)?;
+        let (header, data) = PackHeader::read_pack(&stored)?;
+
+        let ecc = ECC::build_by_name(&header.ecc)?;
+        let (corrected, report) = ecc.read_with_report(data.to_vec())?;
+        health.report = report;
+
+        if report.uncorrectable > 0 {
+            return Ok(());
+        }
+
+        // The ECC is deterministic, so this is the same data that was originally stored and the checksums in parity
+        // groups and PAR2 files still match it
+        let (_, data) = ecc.write(corrected)?;
+        let rebuilt = header.write_pack(&data);
+
+        let header_size = (rebuilt.len() - data.len() - 4) / 2;
+        let copies = [(0, header_size), (rebuilt.len() - header_size - 4, rebuilt.len())];
+        for (start, end) in copies {
+            health.report.blocks += 1;
+            if stored.get(start..end) != Some(&rebuilt[start..end]) {
+                health.report.corrected += 1;
+            }
+        }
+
+        if rebuilt != stored {
+            self.storage.write(name, &rebuilt)?;
+            health.rewritten = true;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecc::ECC;
+    use crate::pack::header::PackHeader;
+    use crate::repository::Repository;
+    use crate::storage::Storage;
+
+    #[test]
+    fn rewrites_corrected_packs() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let repository = Repository::new(Storage::build_local(&dir).unwrap());
+        let storage = repository.get_storage();
+        let ecc = ECC::build_by_name("Reed-Solomon (light)").unwrap();
+
+        let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+        let (_, data) = ecc.write(data).unwrap();
+        let header = PackHeader {
+            hash: vec![1],
+            hasher: "Blake3".to_string(),
+            chunks_size: 300_000,
+            compressor: "None".to_string(),
+            compression_level: None,
+            encryptor: "None".to_string(),
+            ecc: ecc.get_name().to_string(),
+            data_size: data.len() as u64,
+        };
+        let stored = header.write_pack(&data);
+        let good = repository.write_pack(&[1], &stored).unwrap();
+        let corrected = repository.write_pack(&[2], &stored).unwrap();
+        let damaged = repository.write_pack(&[3], &stored).unwrap();
+
+        // 3 stripes of 34 interleaved shards of 4100 bytes
+        let header_size = (stored.len() - data.len() - 4) / 2;
+        let shard = |stripe: usize, shard: usize| header_size + 12 + (shard * 3 + stripe) * 4100;
+
+        let mut data = stored.clone();
+        data[5] ^= 0x01;
+        data[shard(2, 7) + 100] ^= 0x01;
+        storage.write(&corrected, &data).unwrap();
+
+        let mut data = stored.clone();
+        for i in 0..3 {
+            data[shard(1, i)..shard(1, i) + 4100].fill(0);
+        }
+        storage.write(&damaged, &data).unwrap();
+
+        let report = repository.scrub().unwrap();
+
+        assert_eq!(
+            vec![good, corrected.clone(), damaged.clone()],
+            report
+                .packs
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(!report.packs[0].rewritten && !report.packs[0].is_damaged());
+        assert!(report.packs[1].rewritten && !report.packs[1].is_damaged());
+        assert_eq!(2, report.packs[1].report.corrected);
+        assert!(!report.packs[2].rewritten && report.packs[2].is_damaged());
+        assert_eq!(3, report.packs[2].report.uncorrectable);
+        assert_eq!((1, 1), (report.get_rewritten(), report.get_damaged()));
+        assert_eq!(stored, storage.read(&corrected).unwrap());
+    }
+}
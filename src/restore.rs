@@ -0,0 +1,210 @@
+use std::fs;
+use std::fs::Metadata;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Error, Result};
+use relative_path::RelativePathBuf;
+
+use crate::pack::index::ChunkIndex;
+use crate::pack::reader::PackReader;
+use crate::repository::Repository;
+use crate::snapshot::attributes::Ownership;
+use crate::snapshot::builder::{PathBuilder, SnapshotBuilder};
+use crate::snapshot::kind::PathKind;
+
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub failed: Vec<(RelativePathBuf, Error)>,
+}
+
+/// Restores files from the repository. Every pack read is verified against its hash, so a file is either restored
+/// with its original contents or reported as failed.
+pub struct Restorer {
+    index: Arc<ChunkIndex>,
+    reader: PackReader,
+    ownership: Ownership,
+}
+
+impl Restorer {
+    pub fn new(repository: Arc<Repository>, index: Arc<ChunkIndex>, password: &str) -> Restorer {
+        Restorer {
+            index,
+            reader: PackReader::new(repository, password),
+            ownership: Ownership::default(),
+        }
+    }
+
+    pub fn set_ownership(&mut self, ownership: Ownership) {
+        self.ownership = ownership;
+    }
+
+    pub fn restore_snapshot(&mut self, snapshot: &SnapshotBuilder, target: &Path) -> Result<RestoreReport> {
+        let mut report = RestoreReport::default();
+
+        fs::create_dir_all(target)?;
+
+        let mut dirs = Vec::new();
+
+        for path in snapshot.get_paths() {
+            match self.restore_path(&path, target) {
+                Ok(_) if path.get_kind() == Some(PathKind::Dir) => dirs.push(path),
+                Ok(_) => report.restored += 1,
+                Err(e) => report.failed.push((path.get_relative_path().to_owned(), e)),
+            }
+        }
+
+        // Deepest first, so restoring a folder's contents or a read only parent doesn't get in the way
+        for dir in dirs.iter().rev() {
+            match self.restore_attributes(dir, target) {
+                Ok(_) => report.restored += 1,
+                Err(e) => report.failed.push((dir.get_relative_path().to_owned(), e)),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Restores the permissions, owner, times and extended attributes of a path that was already restored.
+    pub fn restore_attributes(&self, path: &PathBuilder, target: &Path) -> Result<()> {
+        let Some(attributes) = path.get_attributes() else {
+            return Ok(());
+        };
+
+        let target = path.get_relative_path().to_logical_path(target);
+
+        attributes
+            .apply(&target, path.get_kind() == Some(PathKind::Symlink), self.ownership)
+            .context("error restoring attributes")
+    }
+
+    /// Restores a path, with its attributes. Folders are only created, because their attributes must be restored
+    /// after their contents, with `restore_attributes`.
+    pub fn restore_path(&mut self, path: &PathBuilder, target: &Path) -> Result<()> {
+        let root = target;
+        let target = path.get_relative_path().to_logical_path(root);
+
+        let kind = path.get_kind().context("path has no metadata")?;
+
+        if kind == PathKind::Dir {
+            fs::create_dir_all(&target)?;
+            return Ok(());
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if kind == PathKind::Symlink {
+            let link_target = path.get_link_target().context("symlink has no target")?;
+            restore_symlink(&link_target, &target)?;
+        } else if let Some(first) = path.get_hard_link() {
+            restore_hard_link(&first.to_logical_path(root), &target)?;
+        } else if kind.is_special() {
+            restore_special(kind, path.get_metadata().unwrap(), &target)?;
+        } else {
+            let result = self.restore_file(path, &target);
+            if result.is_err() {
+                let _ = fs::remove_file(&target);
+            }
+            result?;
+        }
+
+        self.restore_attributes(path, root)
+    }
+
+    /// Sparse chunks are skipped over, so they become holes.
+    fn restore_file(&mut self, path: &PathBuilder, target: &Path) -> Result<()> {
+        let mut file = fs::File::create(target)?;
+        let mut size = 0;
+
+        for chunk in path.get_chunks() {
+            size += chunk.get_size() as u64;
+
+            if chunk.is_sparse() {
+                file.seek(SeekFrom::Current(chunk.get_size() as i64))?;
+                continue;
+            }
+
+            let reader = &mut self.reader;
+            let data = self
+                .index
+                .read_chunk(&chunk.get_hash(), &mut |location| reader.read_chunk(location))
+                .with_context(|| format!("error reading chunk {}", chunk.get_index()))?;
+
+            file.write_all(&data)?;
+        }
+
+        // Ending with a hole
+        file.set_len(size)?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Replaces whatever is at `path` with the link.
+fn restore_symlink(link_target: &Path, path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(path)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(link_target, path)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(link_target, path)?;
+
+    Ok(())
+}
+
+/// Replaces whatever is at `path` with another link to `original`, that must have been restored before.
+fn restore_hard_link(original: &Path, path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(path)?;
+    }
+
+    fs::hard_link(original, path).with_context(|| format!("error linking to {}", original.display()))?;
+
+    Ok(())
+}
+
+/// Replaces whatever is at `path` with a new FIFO, socket or device. Creating devices usually needs root, so this
+/// fails without it.
+#[cfg(unix)]
+fn restore_special(kind: PathKind, metadata: &Metadata, path: &Path) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::UnixListener;
+
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(path)?;
+    }
+
+    let permissions = (metadata.mode() & 0o7777) as libc::mode_t;
+    let (file_type, device) = match kind {
+        PathKind::Socket => {
+            // The socket file stays after the listener is closed
+            UnixListener::bind(path)?;
+            return Ok(());
+        }
+        PathKind::Fifo => (libc::S_IFIFO, 0),
+        PathKind::BlockDevice { major, minor } => (libc::S_IFBLK, libc::makedev(major, minor)),
+        PathKind::CharDevice { major, minor } => (libc::S_IFCHR, libc::makedev(major, minor)),
+        _ => return Err(Error::msg(format!("{:?} is not a special file", kind))),
+    };
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mknod(c_path.as_ptr(), file_type | permissions, device) } != 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("error creating {:?}", kind));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_special(kind: PathKind, _metadata: &Metadata, _path: &Path) -> Result<()> {
+    Err(Error::msg(format!("{:?} can't be restored on this platform", kind)))
+}
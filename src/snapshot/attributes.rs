@@ -0,0 +1,288 @@
+use std::fs::Metadata;
+use std::path::Path;
+
+use anyhow::Result;
+
+/// A point in time, with nanoseconds, as stored by the file system.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanos: i64,
+}
+
+/// The POSIX metadata of a path: permissions, owner, times and extended attributes. On Linux the ACLs are stored as
+/// the `system.posix_acl_access` and `system.posix_acl_default` extended attributes, so they are kept with them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathAttributes {
+    /// The permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+    /// Can't be restored, but tells if the file changed.
+    pub changed: Timestamp,
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Ownership {
+    /// Restore the owners only when running as root, because no one else can give files away.
+    #[default]
+    IfRoot,
+    Always,
+    Never,
+}
+
+#[cfg(unix)]
+impl PathAttributes {
+    /// Reads the attributes of what `metadata` describes: a symlink itself if it is from `fs::symlink_metadata`, or
+    /// its target if it is from `fs::metadata`.
+    pub fn read(path: &Path, metadata: &Metadata) -> Result<PathAttributes> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(PathAttributes {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            accessed: Timestamp {
+                seconds: metadata.atime(),
+                nanos: metadata.atime_nsec(),
+            },
+            modified: Timestamp {
+                seconds: metadata.mtime(),
+                nanos: metadata.mtime_nsec(),
+            },
+            changed: Timestamp {
+                seconds: metadata.ctime(),
+                nanos: metadata.ctime_nsec(),
+            },
+            xattrs: xattrs::list(path, !metadata.is_symlink())?,
+        })
+    }
+
+    /// Applies the attributes to `path`, without following symlinks. The owner goes first, because changing it clears
+    /// the setuid and setgid bits, and the times go last, because the other changes could touch them.
+    pub fn apply(&self, path: &Path, is_symlink: bool, ownership: Ownership) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        use anyhow::Context;
+
+        let c_path = c_path(path)?;
+
+        let is_root = unsafe { libc::geteuid() == 0 };
+
+        let chown = match ownership {
+            Ownership::IfRoot => is_root,
+            Ownership::Always => true,
+            Ownership::Never => false,
+        };
+        if chown && unsafe { libc::lchown(c_path.as_ptr(), self.uid, self.gid) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("error changing owner");
+        }
+
+        // Symlinks have no permissions of their own on Linux
+        if !is_symlink {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(self.mode))
+                .context("error changing permissions")?;
+        }
+
+        for (name, value) in &self.xattrs {
+            match xattrs::set(&c_path, name, value) {
+                // Only root can set these, so everyone else restores what they can
+                Err(_) if !is_root && (name.starts_with(b"trusted.") || name.starts_with(b"security.")) => {}
+                result => {
+                    result.with_context(|| format!("error setting attribute {}", String::from_utf8_lossy(name)))?
+                }
+            }
+        }
+
+        let times = [self.accessed, self.modified].map(|t| libc::timespec {
+            tv_sec: t.seconds as libc::time_t,
+            tv_nsec: t.nanos as _,
+        });
+        let result =
+            unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error()).context("error changing times");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+impl PathAttributes {
+    /// Only the unix attributes are supported.
+    pub fn read(_path: &Path, _metadata: &Metadata) -> Result<PathAttributes> {
+        Ok(PathAttributes::default())
+    }
+
+    pub fn apply(&self, _path: &Path, _is_symlink: bool, _ownership: Ownership) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(std::ffi::CString::new(path.as_os_str().as_bytes())?)
+}
+
+#[cfg(target_os = "linux")]
+mod xattrs {
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::path::Path;
+
+    use anyhow::{Context, Result};
+
+    type ListFn = unsafe extern "C" fn(*const libc::c_char, *mut libc::c_char, libc::size_t) -> libc::ssize_t;
+    type GetFn = unsafe extern "C" fn(
+        *const libc::c_char,
+        *const libc::c_char,
+        *mut libc::c_void,
+        libc::size_t,
+    ) -> libc::ssize_t;
+
+    /// All the extended attributes of a path, by name, of the symlink target if `follow`. File systems without them
+    /// have none.
+    pub fn list(path: &Path, follow: bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let c_path = super::c_path(path)?;
+        let (list, get) = if follow {
+            (libc::listxattr as ListFn, libc::getxattr as GetFn)
+        } else {
+            (libc::llistxattr as ListFn, libc::lgetxattr as GetFn)
+        };
+
+        let names = match read(|buf, size| unsafe { list(c_path.as_ptr(), buf, size) }) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+            result => result.context("error listing attributes")?,
+        };
+
+        let mut result = Vec::new();
+        for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+            let c_name = CString::new(name)?;
+            let value =
+                read(|buf, size| unsafe { get(c_path.as_ptr(), c_name.as_ptr(), buf as *mut libc::c_void, size) });
+
+            match value {
+                // Removed since it was listed
+                Err(e) if e.raw_os_error() == Some(libc::ENODATA) => {}
+                value => result.push((
+                    name.to_vec(),
+                    value.with_context(|| format!("error reading attribute {}", String::from_utf8_lossy(name)))?,
+                )),
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn set(c_path: &CStr, name: &[u8], value: &[u8]) -> io::Result<()> {
+        let c_name = CString::new(name)?;
+        let result = unsafe {
+            libc::lsetxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0)
+        };
+
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Calls a `*xattr` function that fills a buffer, first to get the size and then to fill it. Retries if the size
+    /// changed in between.
+    fn read(mut f: impl FnMut(*mut libc::c_char, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = f(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut buf = vec![0u8; size as usize];
+            let read = f(buf.as_mut_ptr() as *mut libc::c_char, buf.len());
+            if read >= 0 {
+                buf.truncate(read as usize);
+                return Ok(buf);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod xattrs {
+    use std::ffi::CStr;
+    use std::io;
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    /// Extended attributes are only supported on Linux.
+    pub fn list(_path: &Path, _follow: bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    pub fn set(_c_path: &CStr, _name: &[u8], _value: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::snapshot::attributes::{Ownership, PathAttributes, Timestamp};
+
+    #[test]
+    fn reads_and_applies_attributes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (source, target) = (dir.join("source"), dir.join("target"));
+        std::fs::write(&source, "data").unwrap();
+        std::fs::write(&target, "data").unwrap();
+
+        // Not all file systems support user attributes
+        let c_source = super::c_path(&source).unwrap();
+        let has_xattrs = super::xattrs::set(&c_source, b"user.mfsb", b"value").is_ok();
+
+        let mut attributes = PathAttributes::read(&source, &std::fs::symlink_metadata(&source).unwrap()).unwrap();
+        attributes.mode = 0o640;
+        attributes.modified = Timestamp {
+            seconds: 1_000_000_000,
+            nanos: 123_456_789,
+        };
+        attributes.apply(&target, false, Ownership::IfRoot).unwrap();
+
+        let restored = PathAttributes::read(&target, &std::fs::symlink_metadata(&target).unwrap()).unwrap();
+        assert_eq!(attributes.mode, restored.mode);
+        assert_eq!(attributes.modified, restored.modified);
+        assert_eq!(attributes.accessed, restored.accessed);
+        assert_eq!((attributes.uid, attributes.gid), (restored.uid, restored.gid));
+        if has_xattrs {
+            assert_eq!(vec![(b"user.mfsb".to_vec(), b"value".to_vec())], restored.xattrs);
+        }
+    }
+
+    #[test]
+    fn reads_the_attributes_the_metadata_is_from() {
+        let temp = tempfile::tempdir().unwrap();
+        let (file, link) = (temp.path().join("file"), temp.path().join("link"));
+        std::fs::write(&file, "data").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        let c_file = super::c_path(&file).unwrap();
+        super::xattrs::set(&c_file, b"user.mfsb", b"value").expect("the temp dir must support user attributes");
+
+        let followed = PathAttributes::read(&link, &std::fs::metadata(&link).unwrap()).unwrap();
+        assert_eq!(vec![(b"user.mfsb".to_vec(), b"value".to_vec())], followed.xattrs);
+
+        // Symlinks can't have user attributes
+        let own = PathAttributes::read(&link, &std::fs::symlink_metadata(&link).unwrap()).unwrap();
+        assert!(own.xattrs.is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::Metadata;
 use std::path::{Path, PathBuf};
 use std::sync::{atomic, Arc, Mutex};
@@ -7,29 +9,46 @@ use anyhow::Error;
 use relative_path::{RelativePath, RelativePathBuf};
 
 use crate::pack::location::PackLocation;
+use crate::path_walk::{SkipReason, WalkOptions};
+use crate::snapshot::attributes::PathAttributes;
+use crate::snapshot::kind::PathKind;
+use crate::snapshot::source::{SnapshotSource, StreamSource};
 use crate::workspace::SharedItem;
 
+const MAX_SPARSE_CHUNK_SIZE: u32 = 1 << 30;
+
 pub struct SnapshotBuilder {
-    root: Arc<SharedItem>,
+    source: SnapshotSource,
     paths: Mutex<Vec<Arc<PathBuilder>>>,
     paths_count: atomic::AtomicI32,
+    skipped: Mutex<Vec<(RelativePathBuf, SkipReason)>>,
     error: Mutex<Option<Error>>,
     start: Instant,
 }
 
 impl SnapshotBuilder {
-    pub fn new(root: SharedItem) -> Arc<SnapshotBuilder> {
+    pub fn new(root: SharedItem, options: WalkOptions) -> Arc<SnapshotBuilder> {
+        Self::with_source(SnapshotSource::Folder(Arc::new(root), options))
+    }
+
+    /// A snapshot with a single virtual file named `name`, containing the data read from `source`.
+    pub fn new_stream(name: RelativePathBuf, source: StreamSource) -> Arc<SnapshotBuilder> {
+        Self::with_source(SnapshotSource::Stream(name, source))
+    }
+
+    fn with_source(source: SnapshotSource) -> Arc<SnapshotBuilder> {
         Arc::new(SnapshotBuilder {
-            root: Arc::new(root),
+            source,
             paths: Mutex::new(Vec::new()),
             paths_count: atomic::AtomicI32::new(-1),
+            skipped: Mutex::new(Vec::new()),
             error: Mutex::new(None),
             start: Instant::now(),
         })
     }
 
-    pub fn get_root(&self) -> &Path {
-        &self.root.path
+    pub fn get_source(&self) -> &SnapshotSource {
+        &self.source
     }
 
     pub fn add_path(
@@ -38,13 +57,26 @@ impl SnapshotBuilder {
         relative_path: RelativePathBuf,
         metadata: Option<Metadata>,
     ) -> Arc<PathBuilder> {
-        let result = PathBuilder::new(path, relative_path, metadata);
+        let result = PathBuilder::new(path, relative_path, metadata, false);
+
+        self.paths.lock().unwrap().push(result.clone());
+
+        result
+    }
+
+    /// Adds a file that does not exist in the file system, with data that comes from somewhere else.
+    pub fn add_virtual_file(&self, relative_path: RelativePathBuf) -> Arc<PathBuilder> {
+        let result = PathBuilder::new(relative_path.to_path(""), relative_path, None, true);
 
         self.paths.lock().unwrap().push(result.clone());
 
         result
     }
 
+    pub fn get_paths(&self) -> Vec<Arc<PathBuilder>> {
+        self.paths.lock().unwrap().clone()
+    }
+
     pub fn set_finished_adding_paths(&self, path_count: u32) {
         assert_eq!(path_count, self.paths.lock().unwrap().len() as u32);
 
@@ -52,10 +84,53 @@ impl SnapshotBuilder {
             .store(path_count as i32, atomic::Ordering::SeqCst);
     }
 
+    /// Paths left out of the snapshot, and why.
+    pub fn add_skipped_paths(&self, paths: Vec<(RelativePathBuf, SkipReason)>) {
+        self.skipped.lock().unwrap().extend(paths);
+    }
+
+    pub fn get_skipped_paths(&self) -> Vec<(RelativePathBuf, SkipReason)> {
+        self.skipped.lock().unwrap().clone()
+    }
+
+    pub fn get_summary(&self) -> SnapshotSummary {
+        let mut summary = SnapshotSummary::default();
+
+        for (_, reason) in self.skipped.lock().unwrap().iter() {
+            *summary.skipped.entry(*reason).or_default() += 1;
+        }
+
+        for path in self.paths.lock().unwrap().iter() {
+            match path.get_kind() {
+                _ if path.has_error() => summary.failed += 1,
+                Some(PathKind::Dir) => summary.dirs += 1,
+                Some(PathKind::Symlink) => summary.links += 1,
+                Some(kind) if kind.is_special() => summary.special += 1,
+                _ => {
+                    summary.files += 1;
+                    if path.get_change_check() == Some(ChangeCheck::Changed) {
+                        summary.changed += 1;
+                    }
+                    summary.bytes += path
+                        .get_chunks()
+                        .iter()
+                        .map(|c| c.get_size() as u64)
+                        .sum::<u64>();
+                }
+            }
+        }
+
+        summary
+    }
+
     pub fn set_error(&self, err: Error) {
         *self.error.lock().unwrap() = Some(err);
     }
 
+    pub fn take_error(&self) -> Option<Error> {
+        self.error.lock().unwrap().take()
+    }
+
     pub fn get_elapsed_time(&self) -> Duration {
         Instant::now() - self.start
     }
@@ -66,10 +141,54 @@ impl SnapshotBuilder {
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotSummary {
+    pub files: usize,
+    pub dirs: usize,
+    pub links: usize,
+    pub special: usize,
+    pub bytes: u64,
+    pub failed: usize,
+    /// Files kept even though they changed while they were read.
+    pub changed: usize,
+    pub skipped: BTreeMap<SkipReason, usize>,
+}
+
+impl fmt::Display for SnapshotSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files ({} bytes), {} folders, {} links and {} special files backed up, {} failed, {} changed while read",
+            self.files, self.bytes, self.dirs, self.links, self.special, self.failed, self.changed
+        )?;
+
+        for (reason, count) in &self.skipped {
+            write!(f, ", {} {}", count, reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What was found when checking if a file changed while it was read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChangeCheck {
+    Unchanged,
+    /// It changed, but then it was read again this many times, until it didn't.
+    UnchangedAfterRetries(u32),
+    /// It changed, and what was read was kept. The data may be inconsistent.
+    Changed,
+}
+
 pub struct PathBuilder {
     path: PathBuf,
     relative_path: RelativePathBuf,
     metadata: Option<Metadata>,
+    is_virtual: bool,
+    link_target: Mutex<Option<PathBuf>>,
+    attributes: Mutex<Option<PathAttributes>>,
+    hard_link: Mutex<Option<RelativePathBuf>>,
+    change_check: Mutex<Option<ChangeCheck>>,
     chunks: Mutex<Vec<Arc<ChunkBuilder>>>,
     chunk_count: atomic::AtomicI32,
     error: Mutex<Option<Error>>,
@@ -77,11 +196,21 @@ pub struct PathBuilder {
 }
 
 impl PathBuilder {
-    fn new(path: PathBuf, relative_path: RelativePathBuf, metadata: Option<Metadata>) -> Arc<PathBuilder> {
+    fn new(
+        path: PathBuf,
+        relative_path: RelativePathBuf,
+        metadata: Option<Metadata>,
+        is_virtual: bool,
+    ) -> Arc<PathBuilder> {
         Arc::new(PathBuilder {
             path,
             relative_path,
             metadata,
+            is_virtual,
+            link_target: Mutex::new(None),
+            attributes: Mutex::new(None),
+            hard_link: Mutex::new(None),
+            change_check: Mutex::new(None),
             chunks: Mutex::new(Vec::new()),
             chunk_count: atomic::AtomicI32::new(-1),
             error: Mutex::new(None),
@@ -101,16 +230,97 @@ impl PathBuilder {
         self.metadata.as_ref()
     }
 
+    pub fn get_kind(&self) -> Option<PathKind> {
+        match self.is_virtual {
+            true => Some(PathKind::File),
+            false => self.metadata.as_ref().map(PathKind::from_metadata),
+        }
+    }
+
+    /// A virtual file has no path or metadata in the file system.
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+
+    /// Where a symlink points to.
+    pub fn get_link_target(&self) -> Option<PathBuf> {
+        self.link_target.lock().unwrap().clone()
+    }
+
+    pub fn set_link_target(&self, target: PathBuf) {
+        *self.link_target.lock().unwrap() = Some(target);
+    }
+
+    /// Another path of the snapshot that is the same file. Its data is stored only there.
+    pub fn get_hard_link(&self) -> Option<RelativePathBuf> {
+        self.hard_link.lock().unwrap().clone()
+    }
+
+    pub fn set_hard_link(&self, path: RelativePathBuf) {
+        *self.hard_link.lock().unwrap() = Some(path);
+    }
+
+    /// The permissions, owner, times and extended attributes, read during the walk. Virtual files have none.
+    pub fn get_attributes(&self) -> Option<PathAttributes> {
+        self.attributes.lock().unwrap().clone()
+    }
+
+    pub fn set_attributes(&self, attributes: PathAttributes) {
+        *self.attributes.lock().unwrap() = Some(attributes);
+    }
+
+    pub fn get_chunks(&self) -> Vec<Arc<ChunkBuilder>> {
+        self.chunks.lock().unwrap().clone()
+    }
+
     pub fn add_chunk(&self, size: u32) -> Arc<ChunkBuilder> {
         let mut chunks = self.chunks.lock().unwrap();
 
-        let result = ChunkBuilder::new(chunks.len() as u32, size);
+        let result = ChunkBuilder::new(chunks.len() as u32, size, false);
 
         chunks.push(result.clone());
 
         return result;
     }
 
+    /// Adds a range of zeros, that is not stored. Large ones become several sparse chunks.
+    pub fn add_hole(&self, size: u64) {
+        let mut chunks = self.chunks.lock().unwrap();
+
+        let mut remaining = size;
+        while remaining > 0 {
+            let size = remaining.min(MAX_SPARSE_CHUNK_SIZE as u64) as u32;
+            let chunk = ChunkBuilder::new(chunks.len() as u32, size, true);
+            chunks.push(chunk);
+            remaining -= size as u64;
+        }
+    }
+
+    /// Forgets the chunks, to read the file again. The ones already sent are discarded: they are only stored if they
+    /// were already packed, and errors storing them are not reported against the file, even the ones before this.
+    pub fn reset_chunks(&self) {
+        let mut chunks = self.chunks.lock().unwrap();
+        for chunk in chunks.drain(..) {
+            chunk.discarded.store(true, atomic::Ordering::SeqCst);
+        }
+
+        // While the file is being read, only storing its chunks can fail it
+        *self.error.lock().unwrap() = None;
+    }
+
+    /// If the file changed while it was read. Only files read from the file system are checked.
+    pub fn get_change_check(&self) -> Option<ChangeCheck> {
+        *self.change_check.lock().unwrap()
+    }
+
+    pub fn set_change_check(&self, check: ChangeCheck) {
+        *self.change_check.lock().unwrap() = Some(check);
+    }
+
+    pub fn get_chunk_count(&self) -> u32 {
+        self.chunks.lock().unwrap().len() as u32
+    }
+
     pub fn set_finished_adding_chunks(&self, chunk_count: u32) {
         assert_eq!(chunk_count, self.chunks.lock().unwrap().len() as u32);
 
@@ -122,6 +332,19 @@ impl PathBuilder {
         *self.error.lock().unwrap() = Some(err);
     }
 
+    /// Fails the file because one of its chunks couldn't be stored, unless the chunk was discarded.
+    pub fn set_chunk_error(&self, chunk: &ChunkBuilder, err: Error) {
+        // Checked with the chunks locked, so it can't be discarded in between
+        let _chunks = self.chunks.lock().unwrap();
+        if !chunk.is_discarded() {
+            self.set_error(err);
+        }
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.error.lock().unwrap().is_some()
+    }
+
     pub fn get_elapsed_time(&self) -> Duration {
         Instant::now() - self.start
     }
@@ -136,18 +359,24 @@ impl PathBuilder {
 pub struct ChunkBuilder {
     index: u32,
     size: u32,
+    sparse: bool,
     hash: Mutex<Vec<u8>>,
+    delta_base: Mutex<Option<Vec<u8>>>,
     pack_location: Mutex<Option<PackLocation>>,
+    discarded: atomic::AtomicBool,
     start: Instant,
 }
 
 impl ChunkBuilder {
-    fn new(index: u32, size: u32) -> Arc<ChunkBuilder> {
+    fn new(index: u32, size: u32, sparse: bool) -> Arc<ChunkBuilder> {
         Arc::new(ChunkBuilder {
             index,
             size,
+            sparse,
             hash: Mutex::new(Vec::new()),
+            delta_base: Mutex::new(None),
             pack_location: Mutex::new(None),
+            discarded: atomic::AtomicBool::new(false),
             start: Instant::now(),
         })
     }
@@ -160,19 +389,46 @@ impl ChunkBuilder {
         self.size
     }
 
+    /// A sparse chunk is all zeros, so it has no hash and is not stored.
+    pub fn is_sparse(&self) -> bool {
+        self.sparse
+    }
+
+    pub fn get_hash(&self) -> Vec<u8> {
+        self.hash.lock().unwrap().clone()
+    }
+
     pub fn set_hash(&self, hash: Vec<u8>) {
         *self.hash.lock().unwrap() = hash;
     }
 
+    pub fn get_delta_base(&self) -> Option<Vec<u8>> {
+        self.delta_base.lock().unwrap().clone()
+    }
+
+    /// The chunk is stored as a delta against the chunk with this hash.
+    pub fn set_delta_base(&self, base: Vec<u8>) {
+        *self.delta_base.lock().unwrap() = Some(base);
+    }
+
+    pub fn get_pack_location(&self) -> Option<PackLocation> {
+        self.pack_location.lock().unwrap().clone()
+    }
+
     pub fn set_stored(&self, pack_location: PackLocation) {
         *self.pack_location.lock().unwrap() = Some(pack_location);
     }
 
+    /// The file was read again after this chunk was created, so it is not part of it anymore.
+    pub fn is_discarded(&self) -> bool {
+        self.discarded.load(atomic::Ordering::SeqCst)
+    }
+
     pub fn get_elapsed_time(&self) -> Duration {
         Instant::now() - self.start
     }
 
     pub fn is_complete(&self) -> bool {
-        return !self.hash.lock().unwrap().is_empty() && self.pack_location.lock().unwrap().is_some();
+        return self.sparse || (!self.hash.lock().unwrap().is_empty() && self.pack_location.lock().unwrap().is_some());
     }
 }
@@ -0,0 +1,60 @@
+use std::fs::Metadata;
+
+/// What a path is, from its metadata.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathKind {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice { major: u32, minor: u32 },
+    CharDevice { major: u32, minor: u32 },
+}
+
+impl PathKind {
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> PathKind {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let file_type = metadata.file_type();
+        let device = || {
+            let rdev = metadata.rdev() as libc::dev_t;
+            unsafe { (libc::major(rdev) as u32, libc::minor(rdev) as u32) }
+        };
+
+        if file_type.is_symlink() {
+            PathKind::Symlink
+        } else if file_type.is_dir() {
+            PathKind::Dir
+        } else if file_type.is_fifo() {
+            PathKind::Fifo
+        } else if file_type.is_socket() {
+            PathKind::Socket
+        } else if file_type.is_block_device() {
+            let (major, minor) = device();
+            PathKind::BlockDevice { major, minor }
+        } else if file_type.is_char_device() {
+            let (major, minor) = device();
+            PathKind::CharDevice { major, minor }
+        } else {
+            PathKind::File
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> PathKind {
+        if metadata.is_symlink() {
+            PathKind::Symlink
+        } else if metadata.is_dir() {
+            PathKind::Dir
+        } else {
+            PathKind::File
+        }
+    }
+
+    /// Special files have no data, only metadata.
+    pub fn is_special(&self) -> bool {
+        !matches!(self, PathKind::File | PathKind::Dir | PathKind::Symlink)
+    }
+}
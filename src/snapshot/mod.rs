@@ -1 +1,4 @@
+pub mod attributes;
 pub mod builder;
+pub mod kind;
+pub mod source;
@@ -0,0 +1,116 @@
+use std::io;
+use std::io::Read;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::{Context, Error, Result};
+use relative_path::RelativePathBuf;
+
+use crate::path_walk::WalkOptions;
+use crate::workspace::SharedItem;
+
+/// Where the data of a snapshot comes from.
+pub enum SnapshotSource {
+    /// All the files inside a shared item.
+    Folder(Arc<SharedItem>, WalkOptions),
+    /// A single virtual file, with the data read from a stream.
+    Stream(RelativePathBuf, StreamSource),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamSource {
+    Stdin,
+    /// A program and its arguments, run without a shell. Its output is the data.
+    Command(Vec<String>),
+}
+
+impl StreamSource {
+    pub fn open(&self) -> Result<Stream> {
+        match self {
+            StreamSource::Stdin => Ok(Stream {
+                stdout: None,
+                child: None,
+            }),
+            StreamSource::Command(args) => {
+                let (program, args) = args.split_first().context("empty command")?;
+
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("error running {}", program))?;
+
+                Ok(Stream {
+                    stdout: child.stdout.take(),
+                    child: Some(child),
+                })
+            }
+        }
+    }
+}
+
+pub struct Stream {
+    stdout: Option<ChildStdout>,
+    child: Option<Child>,
+}
+
+impl Stream {
+    /// Waits for the command to exit, failing if it was not successful. If the data was not read until the end, the
+    /// command is killed instead.
+    pub fn finish(mut self, completed: bool) -> Result<()> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+
+        drop(self.stdout.take());
+
+        if !completed {
+            let _ = child.kill();
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::msg(format!("command failed with {}", status)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stdout.as_mut() {
+            Some(stdout) => stdout.read(buf),
+            None => io::stdin().read(buf),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Read;
+
+    use crate::snapshot::source::StreamSource;
+
+    fn command(args: &[&str]) -> StreamSource {
+        StreamSource::Command(args.iter().map(|a| a.to_string()).collect())
+    }
+
+    #[test]
+    fn reads_command_output_and_checks_its_exit_status() {
+        let mut stream = command(&["sh", "-c", "echo dump"]).open().unwrap();
+        let mut data = String::new();
+        stream.read_to_string(&mut data).unwrap();
+        assert_eq!("dump\n", data);
+        stream.finish(true).unwrap();
+
+        let mut stream = command(&["sh", "-c", "echo partial; exit 3"])
+            .open()
+            .unwrap();
+        stream.read_to_string(&mut String::new()).unwrap();
+        assert!(stream.finish(true).is_err());
+
+        assert!(command(&["mfsb-no-such-command"]).open().is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::*;
+
+/// Which damage to inject into the objects written to a storage. The damage only depends on the seed and the name
+/// of the object, so runs are reproducible.
+#[derive(Clone, Debug)]
+pub struct FaultProfile {
+    pub seed: u64,
+    /// Only objects with names that start with this are damaged.
+    pub prefix: String,
+    /// Number of random bits flipped in each object.
+    pub bit_flips: usize,
+    /// Number of random ranges of `zeroed_range_size` bytes zeroed in each object.
+    pub zeroed_ranges: usize,
+    pub zeroed_range_size: usize,
+    /// Probability of an object being truncated at a random position.
+    pub truncate_probability: f64,
+    /// Probability of an object not being written at all.
+    pub drop_probability: f64,
+    /// Number of writes that fail with an error before the others work, like a storage that is down for a while.
+    pub failed_writes: usize,
+}
+
+impl FaultProfile {
+    pub fn new(seed: u64, prefix: &str) -> Self {
+        Self {
+            seed,
+            prefix: prefix.to_string(),
+            bit_flips: 0,
+            zeroed_ranges: 0,
+            zeroed_range_size: 4096,
+            truncate_probability: 0.0,
+            drop_probability: 0.0,
+            failed_writes: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    FlippedBit(usize),
+    ZeroedRange(usize, usize),
+    Truncated(usize),
+    Dropped,
+    Failed,
+}
+
+/// Wraps another storage, damaging objects as they are written, to test the recovery paths. Only the first write of
+/// each object is damaged, so repairs that rewrite objects work as they would with real bit rot.
+pub struct FaultyStorage {
+    inner: Arc<Storage>,
+    profile: FaultProfile,
+    written: Mutex<HashSet<String>>,
+    failed_writes: Mutex<usize>,
+    faults: Mutex<Vec<(String, Fault)>>,
+}
+
+impl FaultyStorage {
+    pub fn new(inner: Arc<Storage>, profile: FaultProfile) -> Self {
+        Self {
+            inner,
+            profile,
+            written: Mutex::new(HashSet::new()),
+            failed_writes: Mutex::new(0),
+            faults: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn damage(&self, name: &str, data: &[u8]) -> Option<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(self.profile.seed ^ crc32fast::hash(name.as_bytes()) as u64);
+        let mut faults = Vec::new();
+
+        if rng.gen_bool(self.profile.drop_probability) {
+            self.record(name, vec![Fault::Dropped]);
+            return None;
+        }
+
+        let mut data = data.to_vec();
+
+        if !data.is_empty() {
+            for _ in 0..self.profile.bit_flips {
+                let bit = rng.gen_range(0..data.len() * 8);
+                data[bit / 8] ^= 1 << (bit % 8);
+                faults.push(Fault::FlippedBit(bit));
+            }
+
+            for _ in 0..self.profile.zeroed_ranges {
+                let start = rng.gen_range(0..data.len());
+                let end = min(start + self.profile.zeroed_range_size, data.len());
+                data[start..end].fill(0);
+                faults.push(Fault::ZeroedRange(start, end));
+            }
+
+            if rng.gen_bool(self.profile.truncate_probability) {
+                let size = rng.gen_range(0..data.len());
+                data.truncate(size);
+                faults.push(Fault::Truncated(size));
+            }
+        }
+
+        self.record(name, faults);
+        Some(data)
+    }
+
+    fn record(&self, name: &str, faults: Vec<Fault>) {
+        let mut all = self.faults.lock().unwrap();
+        all.extend(faults.into_iter().map(|f| (name.to_string(), f)));
+    }
+}
+
+impl StorageImpl for FaultyStorage {
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        if name.starts_with(&self.profile.prefix) {
+            let mut failed = self.failed_writes.lock().unwrap();
+            if *failed < self.profile.failed_writes {
+                *failed += 1;
+                self.record(name, vec![Fault::Failed]);
+                return Err(Error::msg(format!("error writing {}: injected failure", name)));
+            }
+        }
+
+        let first = name.starts_with(&self.profile.prefix) && self.written.lock().unwrap().insert(name.to_string());
+        if !first {
+            return self.inner.write(name, data);
+        }
+
+        match self.damage(name, data) {
+            None => Ok(()),
+            Some(damaged) => self.inner.write(name, &damaged),
+        }
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>> {
+        self.inner.read(name)
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        self.inner.exists(name)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.inner.delete(name)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.inner.list(prefix)
+    }
+
+    fn get_injected_faults(&self) -> Vec<(String, Fault)> {
+        self.faults.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::faulty::{Fault, FaultProfile};
+    use crate::storage::Storage;
+
+    #[test]
+    fn damages_first_write_deterministically() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let local = Storage::build_local(&dir).unwrap();
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+
+        let mut profile = FaultProfile::new(42, "packs/");
+        profile.bit_flips = 3;
+        profile.zeroed_ranges = 1;
+        profile.zeroed_range_size = 100;
+
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let storage = Storage::build_faulty(local.clone(), profile.clone());
+            storage.write("packs/a", &data).unwrap();
+            storage.write("index/a", &data).unwrap();
+            results.push((storage.read("packs/a").unwrap(), storage.get_injected_faults()));
+
+            assert_eq!(data, storage.read("index/a").unwrap());
+
+            storage.write("packs/a", &data).unwrap();
+            assert_eq!(data, storage.read("packs/a").unwrap());
+        }
+
+        assert_eq!(results[0], results[1]);
+        let (damaged, faults) = &results[0];
+        assert_ne!(&data, damaged);
+        assert_eq!(4, faults.len());
+        assert!(faults.iter().all(|(name, _)| name == "packs/a"));
+        assert!(matches!(faults[3].1, Fault::ZeroedRange(..)));
+
+        profile.drop_probability = 1.0;
+        let storage = Storage::build_faulty(local.clone(), profile);
+        storage.write("packs/b", &data).unwrap();
+        assert!(!storage.exists("packs/b").unwrap());
+        assert_eq!(vec![("packs/b".to_string(), Fault::Dropped)], storage.get_injected_faults());
+    }
+}
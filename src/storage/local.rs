@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use uuid::Uuid;
+
+use super::*;
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root).with_context(|| format!("error creating storage folder {:?}", root))?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    fn get_path(&self, name: &str) -> PathBuf {
+        let mut result = self.root.clone();
+        for part in name.split('/') {
+            result.push(part);
+        }
+        result
+    }
+}
+
+impl StorageImpl for LocalStorage {
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        let path = self.get_path(name);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+
+        let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+
+        let result = (|| -> Result<()> {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+
+        result.with_context(|| format!("error writing {}", name))
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>> {
+        fs::read(self.get_path(name)).with_context(|| format!("error reading {}", name))
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.get_path(name).is_file())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.get_path(name)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            r => r.with_context(|| format!("error deleting {}", name)),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+
+        let mut queue = vec![(self.root.clone(), String::new())];
+        while let Some((dir, dir_name)) = queue.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                r => r?,
+            };
+
+            for entry in entries {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.starts_with('.') {
+                    continue;
+                }
+
+                let name = format!("{}{}", dir_name, file_name);
+
+                if entry.file_type()?.is_dir() {
+                    let dir_name = format!("{}/", name);
+                    if dir_name.starts_with(prefix) || prefix.starts_with(&dir_name) {
+                        queue.push((entry.path(), dir_name));
+                    }
+                } else if name.starts_with(prefix) {
+                    result.push(name);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,90 @@
+use std::cmp::min;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+pub mod faulty;
+mod local;
+
+/// Where the repository objects (packs, parity, metadata) are kept. Objects are addressed by names that use `/` as
+/// separator, like `packs/0a/0a1b2c...`.
+pub struct Storage {
+    name: &'static str,
+    st: StorageType,
+    inner: Box<dyn StorageImpl>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StorageType {
+    Local,
+    Faulty,
+}
+
+trait StorageImpl: Send + Sync {
+    fn write(&self, name: &str, data: &[u8]) -> Result<()>;
+    fn read(&self, name: &str) -> Result<Vec<u8>>;
+    fn exists(&self, name: &str) -> Result<bool>;
+    fn delete(&self, name: &str) -> Result<()>;
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+    fn get_injected_faults(&self) -> Vec<(String, faulty::Fault)> {
+        Vec::new()
+    }
+}
+
+impl Storage {
+    pub fn build_local(root: &Path) -> Result<Arc<Storage>> {
+        let inner = local::LocalStorage::new(root)?;
+
+        Ok(Arc::new(Storage::new("Local", StorageType::Local, Box::new(inner))))
+    }
+
+    /// Wraps a storage to damage the objects written to it. Only meant for testing.
+    pub fn build_faulty(inner: Arc<Storage>, profile: faulty::FaultProfile) -> Arc<Storage> {
+        let inner = faulty::FaultyStorage::new(inner, profile);
+
+        Arc::new(Storage::new("Faulty", StorageType::Faulty, Box::new(inner)))
+    }
+
+    fn new(name: &'static str, st: StorageType, inner: Box<dyn StorageImpl>) -> Self {
+        Self { name, st, inner }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get_type(&self) -> StorageType {
+        self.st
+    }
+
+    /// Writes the whole object atomically, replacing it if it exists.
+    pub fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.inner.write(name, data)
+    }
+
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        self.inner.read(name)
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        self.inner.exists(name)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.inner.delete(name)
+    }
+
+    /// Lists the names of all objects that start with `prefix`, sorted.
+    pub fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut result = self.inner.list(prefix)?;
+        result.sort();
+        Ok(result)
+    }
+
+    /// The damage done to the objects written, if this is a faulty storage.
+    pub fn get_injected_faults(&self) -> Vec<(String, faulty::Fault)> {
+        self.inner.get_injected_faults()
+    }
+}
@@ -6,6 +6,9 @@ use directories::ProjectDirs;
 use uuid::Uuid;
 
 use crate::db::workspace_db::WorkspaceDB;
+use crate::path_walk::{PathRules, WalkFilters};
+use crate::repository::Repository;
+use crate::storage::Storage;
 
 #[derive(Clone)]
 pub struct Workspace {
@@ -42,6 +45,30 @@ impl Workspace {
         self.lock_data().get_shared_item(path)
     }
 
+    /// Saves the filters of a shared item, used by its next backups.
+    pub fn update_shared_item(&mut self, item: &SharedItem) -> Result<()> {
+        self.lock_data().workspace_db.shared_items.update(item)
+    }
+
+    /// The repository kept in the local data folder.
+    pub fn get_repository(&mut self) -> Result<Arc<Repository>> {
+        let storage = Storage::build_local(&self.lock_data().data_dir.join("repository"))?;
+
+        Repository::open(storage)
+    }
+
+    /// The rules in the `ignore` file of the config folder, if there is one.
+    pub fn get_path_rules(&mut self) -> Result<PathRules> {
+        let mut rules = PathRules::new();
+
+        let file = self.lock_data().config_dir.join("ignore");
+        if file.is_file() {
+            rules.add_config_file(&file)?;
+        }
+
+        Ok(rules)
+    }
+
     fn lock_data(&mut self) -> MutexGuard<'_, WorkspaceData> {
         self.data.lock().unwrap()
     }
@@ -65,11 +92,16 @@ impl WorkspaceData {
 pub struct SharedItem {
     pub id: Uuid,
     pub path: PathBuf,
+    pub filters: WalkFilters,
 }
 
 impl SharedItem {
     pub fn new(id: Uuid, path: PathBuf) -> Self {
-        Self { id, path }
+        Self {
+            id,
+            path,
+            filters: WalkFilters::default(),
+        }
     }
 
     pub fn build(path: &Path) -> Self {
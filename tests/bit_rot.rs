@@ -0,0 +1,232 @@
+//! Backs up a folder to a storage that damages what is written to it, and checks that the damage is either corrected,
+//! repaired or reported, but never restored as wrong data.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use mfsb::ecc::ECC;
+use mfsb::pack::index::ChunkIndex;
+use mfsb::path_walk::WalkOptions;
+use mfsb::pipeline::{Pipeline, PipelineOptions};
+use mfsb::repository::parity::ParityConfig;
+use mfsb::repository::{Repository, RepositoryOptions};
+use mfsb::restore::{RestoreReport, Restorer};
+use mfsb::snapshot::builder::SnapshotBuilder;
+use mfsb::storage::faulty::{Fault, FaultProfile};
+use mfsb::storage::Storage;
+use mfsb::workspace::SharedItem;
+
+const PASSWORD: &str = "1234";
+
+struct Backup {
+    dir: tempfile::TempDir,
+    source: PathBuf,
+    repository: Arc<Repository>,
+    snapshot: Arc<SnapshotBuilder>,
+    index: Arc<ChunkIndex>,
+}
+
+impl Backup {
+    fn run(profile: FaultProfile) -> Backup {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        create_files(&source, profile.seed);
+
+        let storage = Storage::build_faulty(Storage::build_local(&dir.path().join("repository")).unwrap(), profile);
+        let options = RepositoryOptions {
+            parity: Some(ParityConfig {
+                data_packs: 4,
+                parity_packs: 1,
+            }),
+            ..Default::default()
+        };
+        let repository = Repository::build(storage, options);
+
+        let snapshot = SnapshotBuilder::new(SharedItem::build(&source), WalkOptions::default());
+
+        let options = PipelineOptions {
+            chunk_threads: 4,
+            prepare_threads: 2,
+            ..Default::default()
+        };
+        let (pipeline, tx, rx) = Pipeline::new(options, repository.clone());
+        tx.send(snapshot.clone()).unwrap();
+        drop(tx);
+        for _ in rx {}
+        pipeline.join_threads();
+
+        assert!(snapshot.is_complete());
+
+        let index = pipeline.get_index().clone();
+
+        Backup {
+            dir,
+            source,
+            repository,
+            snapshot,
+            index,
+        }
+    }
+
+    fn get_faults(&self) -> Vec<(String, Fault)> {
+        self.repository.get_storage().get_injected_faults()
+    }
+
+    /// Restores everything, checking that all restored files are identical to the originals.
+    fn restore(&self, name: &str) -> RestoreReport {
+        let target = self.dir.path().join(name);
+
+        let mut restorer = Restorer::new(self.repository.clone(), self.index.clone(), PASSWORD);
+        let report = restorer.restore_snapshot(&self.snapshot, &target).unwrap();
+
+        for path in self.snapshot.get_paths() {
+            let relative = path.get_relative_path();
+            if report.failed.iter().any(|(p, _)| p == relative) {
+                assert!(!relative.to_logical_path(&target).is_file(), "failed {} was restored", relative);
+                continue;
+            }
+
+            let original = relative.to_logical_path(&self.source);
+            let restored = relative.to_logical_path(&target);
+            if original.is_file() {
+                assert_eq!(fs::read(&original).unwrap(), fs::read(&restored).unwrap(), "{}", relative);
+            } else {
+                assert!(restored.is_dir(), "{}", relative);
+            }
+        }
+
+        report
+    }
+}
+
+fn create_files(root: &Path, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    fs::create_dir_all(root.join("sub/deeper")).unwrap();
+    fs::write(root.join("empty"), b"").unwrap();
+
+    for (i, name) in ["a.bin", "sub/b.bin", "sub/deeper/c.bin"]
+        .iter()
+        .enumerate()
+    {
+        let mut data = vec![0u8; 100_000 * (i + 1)];
+        rng.fill(&mut data[..]);
+        fs::write(root.join(name), data).unwrap();
+    }
+
+    let text: String = (0..20_000)
+        .map(|i| format!("line {} of a text file\n", i))
+        .collect();
+    fs::write(root.join("sub/text.txt"), text).unwrap();
+}
+
+#[test]
+fn restores_through_corrected_bit_flips() {
+    let mut profile = FaultProfile::new(1, "packs/");
+    profile.bit_flips = 20;
+    let backup = Backup::run(profile);
+
+    assert!(!backup.get_faults().is_empty());
+    assert!(backup.restore("restore").failed.is_empty());
+
+    let report = backup.repository.scrub().unwrap();
+    assert_eq!(0, report.get_damaged());
+    assert_eq!(20, report.get_total().corrected);
+    assert_eq!(report.packs.len(), report.get_rewritten());
+
+    let report = backup.repository.scrub().unwrap();
+    assert_eq!((0, 0), (report.get_total().corrected, report.get_rewritten()));
+}
+
+#[test]
+fn reports_then_repairs_zeroed_ranges() {
+    let mut profile = FaultProfile::new(2, "packs/");
+    profile.zeroed_ranges = 1;
+    let backup = Backup::run(profile);
+
+    // SECDED can't correct this, but the pack hash catches it
+    assert!(!backup.restore("before").failed.is_empty());
+
+    let report = backup.repository.repair().unwrap();
+    assert!(report.failed.is_empty());
+    assert_eq!(1, report.repaired.len());
+
+    assert!(backup.restore("after").failed.is_empty());
+}
+
+#[test]
+fn repairs_dropped_packs() {
+    let mut profile = FaultProfile::new(3, "packs/");
+    profile.drop_probability = 1.0;
+    let backup = Backup::run(profile);
+
+    assert!(backup
+        .get_faults()
+        .iter()
+        .all(|(_, f)| *f == Fault::Dropped));
+    assert!(backup.repository.list_packs().unwrap().is_empty());
+    assert_eq!(4, backup.restore("before").failed.len());
+
+    let report = backup.repository.repair().unwrap();
+    assert!(report.failed.is_empty());
+
+    assert!(backup.restore("after").failed.is_empty());
+}
+
+#[test]
+fn repairs_truncated_packs() {
+    let mut profile = FaultProfile::new(4, "packs/");
+    profile.truncate_probability = 1.0;
+    let backup = Backup::run(profile);
+
+    assert!(!backup.restore("before").failed.is_empty());
+    assert_eq!(1, backup.repository.scrub().unwrap().get_damaged());
+
+    let report = backup.repository.repair().unwrap();
+    assert!(report.failed.is_empty());
+
+    assert!(backup.restore("after").failed.is_empty());
+    assert_eq!(0, backup.repository.scrub().unwrap().get_damaged());
+}
+
+#[test]
+fn ecc_read_never_returns_wrong_data() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut profile = FaultProfile::new(5, "");
+    profile.bit_flips = 4;
+    profile.zeroed_ranges = 2;
+    let storage = Storage::build_faulty(Storage::build_local(&dir).unwrap(), profile);
+    let ecc = ECC::build_by_name("Reed-Solomon").unwrap();
+
+    let mut rng = StdRng::seed_from_u64(5);
+    let mut originals = Vec::new();
+    for i in 0..20 {
+        let mut data = vec![0u8; 300_000];
+        rng.fill(&mut data[..]);
+        storage
+            .write(&i.to_string(), &ecc.write(data.clone()).unwrap().1)
+            .unwrap();
+        originals.push(data);
+    }
+
+    let mut recovered = 0;
+    for (i, original) in originals.iter().enumerate() {
+        let stored = storage.read(&i.to_string()).unwrap();
+        let (_, report) = ecc.read_with_report(stored.clone()).unwrap();
+
+        match ecc.read(stored) {
+            Ok(data) => {
+                assert_eq!(original, &data);
+                assert_eq!(0, report.uncorrectable);
+                recovered += 1;
+            }
+            Err(_) => assert!(report.uncorrectable > 0),
+        }
+    }
+    assert!(recovered >= 18, "only {} recovered", recovered);
+}